@@ -1,14 +1,22 @@
-use anyhow::Result;
-use clap::Parser;
+use crate::{
+    fmt::save::Settings,
+    resolvers::{self, Resolver},
+    status::{self, Error, Status},
+};
+use anyhow::{Result, bail};
+use chrono::DateTime;
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
 use rsdns::{
     clients::{ClientConfig, EDns, ProtocolStrategy, Recursion},
+    message::{RCode, RecordsSection},
     records::Type,
 };
 use std::{
-    net::{IpAddr, SocketAddr},
+    io::IsTerminal,
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
     process::exit,
     str::FromStr,
-    time::Duration,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 #[allow(dead_code)]
@@ -23,17 +31,57 @@ pub enum OutputFormat {
     ZoneRfc3597,
     Short,
     Rust,
+    RustTest,
+    CBytes,
+    Python,
+    Go,
+    Hex,
+    Xxd,
+    Base64,
+    ZoneFile,
+    Dissect,
+}
+
+/// The parts of the zone output to print, toggled like in dig.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Display {
+    pub cmd: bool,
+    pub comments: bool,
+    pub question: bool,
+    pub answer: bool,
+    pub authority: bool,
+    pub additional: bool,
+    pub stats: bool,
+    pub ttlid: bool,
+    pub class: bool,
 }
 
 #[derive(Debug, Parser)]
-#[command(about = "DNS Client", version = env!("CH4_VERSION"))]
+#[command(
+    about = "DNS Client",
+    version = env!("CH4_VERSION"),
+    after_help = status::HELP,
+    args_conflicts_with_subcommands = true,
+    group(ArgGroup::new("input").args(["read_path", "read_pcap"]))
+)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     #[cfg(all(target_os = "linux", feature = "net-tokio", feature = "socket2"))]
-    #[arg(short, long)]
+    #[arg(long)]
     bind_device: Option<String>,
 
+    #[arg(
+        short = 'b',
+        long = "bind",
+        value_name = "ADDR[#PORT]",
+        help = "bind the query socket to a source address and port"
+    )]
+    bind_addr: Option<String>,
+
     #[arg(short, long, default_value = "53")]
-    port: u16,
+    pub port: u16,
 
     #[arg(
         short = 'l',
@@ -51,6 +99,16 @@ pub struct Args {
     )]
     query_timeout: u64,
 
+    #[arg(short = '4', help = "use IPv4 nameservers only")]
+    pub ipv4: bool,
+
+    #[arg(
+        short = '6',
+        conflicts_with = "ipv4",
+        help = "use IPv6 nameservers only"
+    )]
+    pub ipv6: bool,
+
     #[arg(long, help = "Prints build information")]
     info: bool,
 
@@ -70,14 +128,193 @@ pub struct Args {
     pub qnames: Vec<String>,
 
     #[arg(skip)]
-    pub nameservers: Vec<String>,
+    pub nameservers: Vec<SocketAddr>,
+
+    /// Set if the nameservers were given on the command line, not taken from the OS.
+    #[arg(skip)]
+    pub nameservers_given: bool,
+
+    /// The client settings given on the command line, not defaulted.
+    #[arg(skip)]
+    settings_given: Settings,
+
+    /// The EDNS payload size given with `+bufsize`.
+    #[arg(skip)]
+    udp_payload_size_given: Option<u16>,
+
+    #[arg(skip)]
+    pub source: Option<SocketAddr>,
+
+    #[arg(skip)]
+    pub qr: bool,
+
+    #[arg(skip)]
+    pub best_effort: bool,
+
+    #[arg(skip)]
+    pub color: bool,
+
+    #[arg(skip)]
+    pub display: Display,
+
+    #[arg(skip)]
+    pub multiline: bool,
+
+    #[arg(skip)]
+    pub ttl_units: bool,
 
     #[arg(short = 's', long = "save", help = "save responses to file")]
     pub save_path: Option<String>,
 
+    #[arg(
+        long,
+        requires = "save_path",
+        help = "append to the --save file instead of overwriting it"
+    )]
+    pub save_append: bool,
+
     #[arg(short = 'r', long = "read", help = "read responses from file")]
     pub read_path: Option<String>,
 
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "save queries and responses to a pcap file"
+    )]
+    pub save_pcap: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with = "read_path",
+        help = "read DNS messages to and from --port from a pcap or pcapng file"
+    )]
+    pub read_pcap: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["read_path", "read_pcap", "watch", "propagation"],
+        help = "ask the questions saved in FILE again, e.g. @server, and diff the responses"
+    )]
+    pub rerun: Option<String>,
+
+    #[arg(
+        long = "qname",
+        value_name = "GLOB",
+        requires = "input",
+        help = "read only the messages with a query name matching GLOB, e.g. *.example.com"
+    )]
+    pub filter_qname: Option<String>,
+
+    #[arg(
+        long = "qtype",
+        value_name = "TYPE",
+        requires = "input",
+        value_parser = parse_qtype,
+        help = "read only the messages of query type TYPE; may be repeated"
+    )]
+    pub filter_qtype: Vec<Type>,
+
+    #[arg(
+        long = "rcode",
+        value_name = "RCODE",
+        requires = "input",
+        value_parser = parse_rcode,
+        help = "read only the responses with response code RCODE; may be repeated"
+    )]
+    pub filter_rcode: Vec<RCode>,
+
+    #[arg(
+        long = "server",
+        value_name = "ADDR[#PORT]",
+        requires = "input",
+        value_parser = parse_server,
+        help = "read only the responses from nameserver ADDR; may be repeated"
+    )]
+    pub filter_server: Vec<SocketAddr>,
+
+    #[arg(
+        long,
+        value_name = "TIME",
+        requires = "input",
+        value_parser = parse_time,
+        help = "read only the messages sent at or after TIME (RFC 3339 or UNIX seconds)"
+    )]
+    pub since: Option<SystemTime>,
+
+    #[arg(
+        long,
+        value_name = "TIME",
+        requires = "input",
+        value_parser = parse_time,
+        help = "read only the messages sent before TIME (RFC 3339 or UNIX seconds)"
+    )]
+    pub until: Option<SystemTime>,
+
+    #[arg(
+        long,
+        value_name = "MSEC",
+        requires = "input",
+        help = "read only the responses that took at least MSEC msec"
+    )]
+    pub min_latency: Option<u64>,
+
+    #[arg(
+        long,
+        requires = "input",
+        help = "print a line per message read, and the number of messages"
+    )]
+    pub summary: bool,
+
+    #[arg(
+        long,
+        value_name = "INTERVAL",
+        conflicts_with_all = ["read_path", "read_pcap"],
+        help = "repeat the queries every INTERVAL msec and report answer changes"
+    )]
+    pub watch: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "watch",
+        help = "stop watching after N iterations"
+    )]
+    pub count: Option<u64>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["read_path", "read_pcap", "watch"],
+        help = "query a list of public resolvers and compare their answers"
+    )]
+    pub propagation: bool,
+
+    #[arg(
+        long = "resolvers",
+        value_name = "FILE",
+        requires = "propagation",
+        help = "read the --propagation resolvers from FILE, one address[#port] [name] per line"
+    )]
+    pub resolvers_path: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "RDATA",
+        requires = "propagation",
+        help = "record data every --propagation resolver is expected to return"
+    )]
+    pub expect: Vec<String>,
+
+    #[arg(skip)]
+    pub resolvers: Vec<Resolver>,
+
+    #[arg(
+        long,
+        help = "exit with a non-zero status if a response code is not NOERROR"
+    )]
+    pub fail_on_rcode: bool,
+
     #[arg(verbatim_doc_comment)]
     /// Positional arguments ...
     ///
@@ -87,9 +324,13 @@ pub struct Args {
     /// to be queried.
     ///
     ///
-    /// @<nameserver> - specifies the nameserver IP address.
-    ///                 If not specified, the first nameserver from OS
-    ///                 configuration is used.
+    /// @<nameserver> - specifies the nameserver IP address or host name.
+    ///                 A port may be appended as @<addr>#<port>, or
+    ///                 @[<ipv6>]:<port>. A host name is resolved using the
+    ///                 system resolver, and its addresses are queried in
+    ///                 turn until one of them responds.
+    ///                 If not specified, the nameservers from OS
+    ///                 configuration are queried in turn.
     ///
     /// <qtype>       - query type (A, AAAA, NS ...).
     ///                 An argument matching any of the supported query types
@@ -121,16 +362,181 @@ pub struct Args {
     ///
     /// +[no]rust     - enables (disables) rust output.
     ///                 When enabled, prints the response as a Rust array.
+    ///                 +rust=test also prints a test parsing the array with
+    ///                 rsdns and asserting the header and the answer records.
+    ///
+    /// +[no]cbytes   - enables (disables) C output.
+    ///                 When enabled, prints the response as a C array.
+    ///
+    /// +[no]python   - enables (disables) Python output.
+    ///                 When enabled, prints the response as Python bytes.
+    ///
+    /// +[no]go       - enables (disables) Go output.
+    ///                 When enabled, prints the response as a Go array.
+    ///
+    /// +[no]hex[=xxd] - enables (disables) hex output.
+    ///                 When enabled, prints the response as hex digits on a
+    ///                 single line, or like xxd with +hex=xxd.
+    ///
+    /// +[no]base64   - enables (disables) base64 output.
+    ///                 When enabled, prints the response in base64 on a
+    ///                 single line.
     ///
     /// +[no]gen      - forces generic output (RFC 3597 s. 5) on all record
     ///                 types. By default, only unknown record types are
     ///                 formatted this way.
+    ///
+    /// +[no]zonefile - enables (disables) zone file output. When enabled,
+    ///                 the answer records of all responses are printed at the end
+    ///                 as an RFC 1035 master file, with $ORIGIN and $TTL,
+    ///                 deduplicated RRsets and relative names.
+    ///
+    /// +[no]dissect  - enables (disables) dissection output. When enabled,
+    ///                 every byte range of the message is shown with its
+    ///                 meaning: header fields, names with their compression
+    ///                 pointers, record fields and record data. Malformed
+    ///                 messages are shown up to the offset where they break.
+    ///
+    /// +[no]qr       - enables (disables) printing of the query message
+    ///                 before the response. rsdns doesn't expose the message
+    ///                 it sends, so the query is rebuilt from the settings and
+    ///                 the response id, and headed "Sending (reconstructed)".
+    ///
+    /// +[no]besteffort - enables (disables) printing of malformed messages.
+    ///                 When enabled, a message failing to parse is printed
+    ///                 up to the failure, followed by the offset where
+    ///                 the message breaks, as shown by +dissect, and the
+    ///                 bytes left from the failing record, in hex.
+    ///
+    /// +[no]multiline - enables (disables) multiline output.
+    ///                 When enabled, SOA, DNSKEY, RRSIG and TXT records with
+    ///                 several strings are printed across lines, with
+    ///                 comments naming the fields.
+    ///
+    /// +[no]ttlunits - enables (disables) printing of TTLs with units,
+    ///                 like 1h30m.
+    ///
+    /// +[no]color    - enables (disables) colored output.
+    ///                 By default, the output is colored if it goes to a
+    ///                 terminal and NO_COLOR is not set.
+    ///
+    /// +[no]cmd, +[no]comments, +[no]question, +[no]answer, +[no]authority,
+    /// +[no]additional, +[no]stats
+    ///               - enable (disable) printing of the command line, the
+    ///                 comments, the sections and the statistics, as in dig.
+    ///
+    /// +[no]all      - enables (disables) all of the above.
+    ///                 +noall +answer prints only the answer records.
+    ///
+    /// +[no]ttlid    - enables (disables) printing of the record TTLs.
+    ///
+    /// +[no]class    - enables (disables) printing of the record classes.
     pub positional: Vec<String>,
 }
 
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Runs a local DNS server answering UDP and TCP queries
+    Serve(ServeArgs),
+    /// Compares the responses of two files saved with --save
+    Diff(DiffArgs),
+    /// Converts the textual output of dig into the format written by --save
+    ///
+    /// Record data of DNAME, SPF, CAA, SSHFP, DS, CDS, DNSKEY, CDNSKEY, TLSA,
+    /// RRSIG, NSEC, SVCB and HTTPS records is encoded from its presentation
+    /// format, as are the types rsdns knows. Other types, e.g. NSEC3, NAPTR or
+    /// LOC, must be printed by dig in the RFC 3597 generic format, with
+    /// +unknownformat. Messages with record data ch4 can't encode are skipped
+    /// with a warning, and the exit status is 11.
+    Import(ImportArgs),
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ServeArgs {
+    #[arg(
+        short = 'r',
+        long = "read",
+        required_unless_present = "zone_path",
+        conflicts_with = "zone_path",
+        help = "answer with the responses saved by --save"
+    )]
+    pub read_path: Option<String>,
+
+    #[arg(
+        long = "zone",
+        value_name = "FILE",
+        help = "answer authoritatively from an RFC 1035 master file"
+    )]
+    pub zone_path: Option<String>,
+
+    #[arg(
+        long,
+        requires = "zone_path",
+        help = "the zone origin, if not set by $ORIGIN in the master file"
+    )]
+    pub origin: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "127.0.0.1:5353",
+        help = "the address to listen on"
+    )]
+    pub listen: SocketAddr,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Unmatched::Refused,
+        help = "the response code of queries with no saved response (with --read)"
+    )]
+    pub unmatched: Unmatched,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct DiffArgs {
+    #[arg(value_name = "A", help = "the file saved before")]
+    pub a: String,
+
+    #[arg(value_name = "B", help = "the file saved after")]
+    pub b: String,
+
+    #[arg(long, help = "print the differences as JSON")]
+    pub json: bool,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ImportArgs {
+    #[arg(long, value_name = "FILE", help = "the file with the output of dig")]
+    pub dig: String,
+
+    #[arg(
+        short = 'o',
+        long,
+        value_name = "FILE",
+        help = "write the messages to FILE instead of stdout"
+    )]
+    pub output: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
+pub enum Unmatched {
+    Refused,
+    Nxdomain,
+}
+
 impl Args {
     pub fn get() -> Result<Args> {
-        let mut args = Args::parse();
+        let mut args = match Args::try_parse() {
+            Ok(args) => args,
+            Err(e) => {
+                e.print()?;
+                exit(if e.use_stderr() {
+                    Status::Usage as i32
+                } else {
+                    0
+                });
+            }
+        };
 
         if args.info {
             Args::show_info();
@@ -142,7 +548,9 @@ impl Args {
             exit(0);
         }
 
-        args.parse_args()?;
+        if args.command.is_none() {
+            args.parse_args()?;
+        }
 
         Ok(args)
     }
@@ -152,6 +560,8 @@ impl Args {
             format!("--save {} ", self.save_path.as_deref().unwrap())
         } else if self.has_read_path() {
             format!("--read {} ", self.read_path.as_deref().unwrap())
+        } else if let Some(ref path) = self.read_pcap {
+            format!("--read-pcap {path} ")
         } else {
             "".to_string()
         };
@@ -174,6 +584,10 @@ impl Args {
         }
     }
 
+    pub fn is_watch(&self) -> bool {
+        self.watch.is_some()
+    }
+
     pub fn qtype(&self) -> Type {
         self.qtype.unwrap()
     }
@@ -202,7 +616,7 @@ impl Args {
         println!("build cpu brand:     {}", env!("CH4_SYSINFO_CPU_BRAND"));
     }
 
-    fn find_rsdns_version() -> &'static str {
+    pub fn find_rsdns_version() -> &'static str {
         for (pkg, version) in bi::DEPENDENCIES {
             if pkg == "rsdns" {
                 return version;
@@ -221,56 +635,83 @@ impl Args {
     }
 
     fn parse_args(&mut self) -> Result<()> {
-        let mut protocol_strategy = ProtocolStrategy::Udp;
-        let mut nameserver_ip_addr: Option<IpAddr> = None;
-        let mut recursion = Recursion::On;
+        let mut protocol_strategy = None;
+        let mut nameservers: Vec<SocketAddr> = Vec::new();
+        let mut recursion = None;
         let mut qnames = Vec::new();
         let mut qtype = Type::A;
         let mut format = OutputFormat::Zone;
-        let mut edns_enabled = true;
+        let mut edns_enabled = None;
         let mut edns_version: u8 = 0;
-        let mut edns_udp_payload_size: u16 = 4096;
+        let mut edns_udp_payload_size = None;
+        let mut color = None;
+
+        if let Some(ref bind_addr) = self.bind_addr {
+            match parse_server_addr(bind_addr, 0) {
+                Some(addr) => self.source = Some(addr),
+                None => bail!(Error::Usage(format!("bad source address: {bind_addr}"))),
+            }
+        }
 
         for a in self.positional.iter() {
+            if self.display.set(a) {
+                continue;
+            }
             match a.as_str() {
-                "+udp" => protocol_strategy = ProtocolStrategy::Udp,
-                "+tcp" => protocol_strategy = ProtocolStrategy::Tcp,
-                "+notcp" => protocol_strategy = ProtocolStrategy::NoTcp,
-                "+rec" => recursion = Recursion::On,
-                "+norec" => recursion = Recursion::Off,
+                "+udp" => protocol_strategy = Some(ProtocolStrategy::Udp),
+                "+tcp" => protocol_strategy = Some(ProtocolStrategy::Tcp),
+                "+notcp" => protocol_strategy = Some(ProtocolStrategy::NoTcp),
+                "+rec" => recursion = Some(Recursion::On),
+                "+norec" => recursion = Some(Recursion::Off),
                 "+short" => format = OutputFormat::Short,
                 "+noshort" => format = OutputFormat::Zone,
                 "+rust" => format = OutputFormat::Rust,
+                "+rust=test" => format = OutputFormat::RustTest,
+                "+cbytes" => format = OutputFormat::CBytes,
+                "+nocbytes" => format = OutputFormat::Zone,
+                "+python" => format = OutputFormat::Python,
+                "+nopython" => format = OutputFormat::Zone,
+                "+go" => format = OutputFormat::Go,
+                "+nogo" => format = OutputFormat::Zone,
+                "+hex" => format = OutputFormat::Hex,
+                "+hex=xxd" => format = OutputFormat::Xxd,
+                "+nohex" => format = OutputFormat::Zone,
+                "+base64" => format = OutputFormat::Base64,
+                "+nobase64" => format = OutputFormat::Zone,
                 "+norust" => format = OutputFormat::Zone,
                 "+gen" => format = OutputFormat::ZoneRfc3597,
                 "+nogen" => format = OutputFormat::Zone,
-                "+noedns" => edns_enabled = false,
+                "+zonefile" => format = OutputFormat::ZoneFile,
+                "+nozonefile" => format = OutputFormat::Zone,
+                "+dissect" => format = OutputFormat::Dissect,
+                "+nodissect" => format = OutputFormat::Zone,
+                "+qr" => self.qr = true,
+                "+noqr" => self.qr = false,
+                "+besteffort" => self.best_effort = true,
+                "+nobesteffort" => self.best_effort = false,
+                "+multiline" => self.multiline = true,
+                "+nomultiline" => self.multiline = false,
+                "+ttlunits" => self.ttl_units = true,
+                "+nottlunits" => self.ttl_units = false,
+                "+color" => color = Some(true),
+                "+nocolor" => color = Some(false),
+                "+noedns" => edns_enabled = Some(false),
                 "+edns" => {
-                    edns_enabled = true;
+                    edns_enabled = Some(true);
                     edns_version = 0
                 }
                 s if s.starts_with("+edns=") => {
-                    edns_enabled = true;
-                    edns_version = get_param_val(s)
+                    edns_enabled = Some(true);
+                    edns_version = get_param_val(s)?
                 }
-                s if s.starts_with("+bufsize=") => edns_udp_payload_size = get_param_val(s),
-                s if s.starts_with('@') => match IpAddr::from_str(&s[1..]) {
-                    Ok(addr) => {
-                        self.nameservers.push(s[1..].to_string());
-                        nameserver_ip_addr = Some(addr)
-                    }
-                    Err(_) => {
-                        eprintln!("failed to parse nameserver ip address");
-                        exit(1);
-                    }
-                },
+                s if s.starts_with("+bufsize=") => edns_udp_payload_size = Some(get_param_val(s)?),
+                s if s.starts_with('@') => nameservers = self.resolve_nameserver(&s[1..])?,
                 s if Type::from_str(&s.to_uppercase()).is_ok() => {
                     qtype = Type::from_str(&s.to_uppercase()).unwrap()
                 }
                 s => {
                     if s.starts_with('+') {
-                        eprintln!("bad option: {s}");
-                        exit(1);
+                        bail!(Error::Usage(format!("bad option: {s}")));
                     }
                     qnames.push(a.clone())
                 }
@@ -278,37 +719,51 @@ impl Args {
         }
 
         self.format = format;
+        self.color = color.unwrap_or_else(color_by_default);
 
         if qtype == Type::OPT || (!qtype.is_data_type() && qtype != Type::ANY) {
-            eprintln!("only data-type queries are supported or ANY: {qtype}");
-            exit(1);
+            bail!(Error::Usage(format!(
+                "only data-type queries are supported or ANY: {qtype}"
+            )));
         }
 
-        let nameserver = match nameserver_ip_addr {
-            Some(addr) => SocketAddr::from((addr, self.port)),
-            None => {
-                if let Ok(v) = crate::os_nameservers() {
-                    SocketAddr::from((v[0], self.port))
-                } else {
-                    eprintln!("no nameservers");
-                    exit(1);
-                }
+        self.nameservers_given = !nameservers.is_empty();
+        if nameservers.is_empty() {
+            if let Ok(v) = crate::os_nameservers() {
+                nameservers = v
+                    .into_iter()
+                    .filter(|addr| self.is_allowed(*addr))
+                    .map(|addr| SocketAddr::from((addr, self.port)))
+                    .collect();
             }
-        };
+            // --propagation queries its own resolvers, --rerun the recorded nameservers
+            if nameservers.is_empty() && !self.propagation && self.rerun.is_none() {
+                bail!(Error::NoServers("no nameservers".to_string()));
+            }
+        }
 
-        let edns = if edns_enabled {
+        let edns = if edns_enabled.unwrap_or(true) {
             EDns::On {
                 version: edns_version,
-                udp_payload_size: edns_udp_payload_size,
+                udp_payload_size: edns_udp_payload_size.unwrap_or(4096),
             }
         } else {
             EDns::Off
         };
+        self.settings_given = Settings {
+            protocol_strategy,
+            recursion,
+            edns: edns_enabled.map(|_| edns),
+        };
+        self.udp_payload_size_given = edns_udp_payload_size;
 
-        #[allow(unused_mut)]
-        let mut config = ClientConfig::with_nameserver(nameserver)
-            .set_protocol_strategy(protocol_strategy)
-            .set_recursion(recursion)
+        let config = match nameservers.first() {
+            Some(nameserver) => ClientConfig::with_nameserver(*nameserver),
+            None => ClientConfig::new(),
+        };
+        let mut config = config
+            .set_protocol_strategy(protocol_strategy.unwrap_or(ProtocolStrategy::Udp))
+            .set_recursion(recursion.unwrap_or(Recursion::On))
             .set_query_timeout(if self.query_timeout > 0 {
                 Some(Duration::from_millis(self.query_timeout))
             } else {
@@ -317,27 +772,163 @@ impl Args {
             .set_query_lifetime(Duration::from_millis(self.query_lifetime))
             .set_edns(edns);
 
+        if let Some(source) = self.source {
+            config = config.set_bind_addr(source);
+        }
+
         #[cfg(all(target_os = "linux", feature = "net-tokio", feature = "socket2"))]
         if let Some(ref bd) = self.bind_device {
             config = config.set_bind_device(Some(bd))?;
         }
 
+        if self.propagation {
+            let resolvers = match self.resolvers_path {
+                Some(ref path) => resolvers::load_resolvers(path, self.port)?,
+                None => resolvers::default_resolvers(self.port),
+            };
+            self.resolvers = resolvers
+                .into_iter()
+                .filter(|r| self.is_allowed(r.addr.ip()))
+                .collect();
+            if self.resolvers.is_empty() {
+                bail!(Error::NoServers(
+                    "no resolvers of the allowed address family".to_string()
+                ));
+            }
+        }
+
         self.config = config;
+        self.nameservers = nameservers;
         self.qtype = Some(qtype);
         self.qnames = qnames;
 
         Ok(())
     }
+
+    /// Returns the client configuration to repeat a saved query with: the recorded
+    /// settings, unless others are given on the command line.
+    pub fn rerun_config(&self, recorded: &Settings) -> ClientConfig {
+        let mut config = self.config.clone();
+        if self.settings_given.protocol_strategy.is_none()
+            && let Some(strategy) = recorded.protocol_strategy
+        {
+            config = config.set_protocol_strategy(strategy);
+        }
+        if self.settings_given.recursion.is_none()
+            && let Some(recursion) = recorded.recursion
+        {
+            config = config.set_recursion(recursion);
+        }
+        if self.settings_given.edns.is_none()
+            && let Some(edns) = recorded.edns
+        {
+            config = config.set_edns(match edns {
+                EDns::On {
+                    version,
+                    udp_payload_size,
+                } => EDns::On {
+                    version,
+                    udp_payload_size: self.udp_payload_size_given.unwrap_or(udp_payload_size),
+                },
+                EDns::Off => EDns::Off,
+            });
+        }
+        config
+    }
+
+    fn resolve_nameserver(&self, s: &str) -> Result<Vec<SocketAddr>> {
+        if let Some(addr) = parse_server_addr(s, self.port) {
+            if !self.is_allowed(addr.ip()) {
+                bail!(Error::NoServers(format!(
+                    "nameserver address family not allowed: {s}"
+                )));
+            }
+            return Ok(vec![addr]);
+        }
+
+        let (host, port) = match s.rsplit_once('#') {
+            Some((host, port)) => match u16::from_str(port) {
+                Ok(port) => (host, port),
+                Err(_) => bail!(Error::Usage(format!("bad nameserver port: {s}"))),
+            },
+            None => (s, self.port),
+        };
+
+        let addrs: Vec<SocketAddr> = match (host, port).to_socket_addrs() {
+            Ok(addrs) => addrs.filter(|a| self.is_allowed(a.ip())).collect(),
+            Err(e) => bail!(Error::NoServers(format!(
+                "failed to resolve nameserver {host}: {e}"
+            ))),
+        };
+
+        if addrs.is_empty() {
+            bail!(Error::NoServers(format!(
+                "no usable addresses for nameserver {host}"
+            )));
+        }
+
+        Ok(addrs)
+    }
+
+    /// Checks the address family against `-4`, `-6` and the source address.
+    fn is_allowed(&self, addr: IpAddr) -> bool {
+        let ipv4 = self.ipv4 || self.source.is_some_and(|s| s.is_ipv4());
+        let ipv6 = self.ipv6 || self.source.is_some_and(|s| s.is_ipv6());
+        !(ipv4 && addr.is_ipv6() || ipv6 && addr.is_ipv4())
+    }
+}
+
+/// Parses a server address with an optional port.
+///
+/// Accepted forms are `addr`, `addr#port` and `ip:port` (`[v6]:port` for IPv6).
+pub fn parse_server_addr(s: &str, default_port: u16) -> Option<SocketAddr> {
+    if let Ok(addr) = IpAddr::from_str(s.trim_start_matches('[').trim_end_matches(']')) {
+        return Some(SocketAddr::from((addr, default_port)));
+    }
+    if let Some((addr, port)) = s.split_once('#') {
+        let addr = addr.trim_start_matches('[').trim_end_matches(']');
+        return match (IpAddr::from_str(addr), u16::from_str(port)) {
+            (Ok(addr), Ok(port)) => Some(SocketAddr::from((addr, port))),
+            _ => None,
+        };
+    }
+    SocketAddr::from_str(s).ok()
+}
+
+fn parse_qtype(s: &str) -> Result<Type, String> {
+    Type::from_str(&s.to_uppercase()).map_err(|_| format!("unknown query type: {s}"))
+}
+
+fn parse_rcode(s: &str) -> Result<RCode, String> {
+    RCode::from_str(&s.to_uppercase()).map_err(|_| format!("unknown response code: {s}"))
+}
+
+fn parse_server(s: &str) -> Result<SocketAddr, String> {
+    parse_server_addr(s, 0).ok_or_else(|| format!("bad nameserver address: {s}"))
+}
+
+fn parse_time(s: &str) -> Result<SystemTime, String> {
+    if let Ok(secs) = u64::from_str(s) {
+        return Ok(UNIX_EPOCH + Duration::from_secs(secs));
+    }
+    match DateTime::parse_from_rfc3339(s) {
+        Ok(dt) => Ok(dt.into()),
+        Err(_) => Err(format!("bad time: {s}")),
+    }
+}
+
+/// Colors are on when the output goes to a terminal, unless `NO_COLOR` is set.
+fn color_by_default() -> bool {
+    std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
 }
 
-fn get_param_val<T: FromStr>(s: &str) -> T {
+fn get_param_val<T: FromStr>(s: &str) -> Result<T> {
     if let Some(p) = s.split('=').nth(1)
         && let Ok(v) = T::from_str(p)
     {
-        return v;
+        return Ok(v);
     }
-    eprintln!("bad option: {s}");
-    exit(1);
+    bail!(Error::Usage(format!("bad option: {s}")));
 }
 
 impl OutputFormat {
@@ -345,3 +936,64 @@ impl OutputFormat {
         self == OutputFormat::ZoneRfc3597
     }
 }
+
+impl Default for Display {
+    fn default() -> Self {
+        Self {
+            cmd: true,
+            comments: true,
+            question: true,
+            answer: true,
+            authority: true,
+            additional: true,
+            stats: true,
+            ttlid: true,
+            class: true,
+        }
+    }
+}
+
+impl Display {
+    /// Applies a `+[no]<flag>` option, returning `false` if it is not a display option.
+    fn set(&mut self, opt: &str) -> bool {
+        let Some(flag) = opt.strip_prefix('+') else {
+            return false;
+        };
+        let (flag, on) = match flag.strip_prefix("no") {
+            Some(flag) => (flag, false),
+            None => (flag, true),
+        };
+        match flag {
+            "cmd" => self.cmd = on,
+            "comments" => self.comments = on,
+            "question" => self.question = on,
+            "answer" => self.answer = on,
+            "authority" => self.authority = on,
+            "additional" => self.additional = on,
+            "stats" => self.stats = on,
+            "ttlid" => self.ttlid = on,
+            "class" => self.class = on,
+            // like in dig, all but ttlid and class
+            "all" => {
+                self.cmd = on;
+                self.comments = on;
+                self.question = on;
+                self.answer = on;
+                self.authority = on;
+                self.additional = on;
+                self.stats = on;
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Checks if the records of a section are printed.
+    pub fn section(&self, section: RecordsSection) -> bool {
+        match section {
+            RecordsSection::Answer => self.answer,
+            RecordsSection::Authority => self.authority,
+            RecordsSection::Additional => self.additional,
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::fmt::Write;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const BLUE: &str = "\x1b[34m";
+const MAGENTA: &str = "\x1b[35m";
+const CYAN: &str = "\x1b[36m";
+
+/// The widths of the record columns in the zone output.
+#[derive(Debug, Clone, Copy)]
+pub struct Columns {
+    pub name: usize,
+    pub ttl: usize,
+    pub rclass: usize,
+    pub rtype: usize,
+}
+
+/// Colors the zone output of a message: the section headings, the response code, and
+/// the owner names, TTLs and types of the questions and records.
+///
+/// The text is colored after it has been formatted, keeping the column widths intact.
+pub fn paint(text: &str, cols: Columns) -> String {
+    let mut output = String::with_capacity(text.len() * 2);
+    for line in text.split_inclusive('\n') {
+        let (line, eol) = match line.strip_suffix('\n') {
+            Some(line) => (line, "\n"),
+            None => (line, ""),
+        };
+        if line.starts_with(";; ->>HEADER<<-") {
+            paint_status(&mut output, line);
+        } else if line.starts_with(";;") && line.ends_with("SECTION:") {
+            paint_str(&mut output, BOLD, line);
+        } else if line.starts_with(";;") || line.starts_with("; ") || line.is_empty() {
+            output.push_str(line);
+        } else if let Some(question) = line.strip_prefix(';') {
+            // the question has no TTL, the name column is narrowed by the semicolon
+            output.push(';');
+            paint_columns(&mut output, question, cols.name - 1, None, cols);
+        } else {
+            paint_columns(&mut output, line, cols.name, Some(BLUE), cols);
+        }
+        output.push_str(eol);
+    }
+    output
+}
+
+fn paint_status(output: &mut String, line: &str) {
+    const STATUS: &str = "status: ";
+    let Some(start) = line.find(STATUS).map(|i| i + STATUS.len()) else {
+        output.push_str(line);
+        return;
+    };
+    let end = line[start..].find(',').map_or(line.len(), |i| start + i);
+    let status = &line[start..end];
+    let color = match status {
+        "NOERROR" => GREEN,
+        "NXDOMAIN" => YELLOW,
+        _ => RED,
+    };
+    output.push_str(&line[..start]);
+    paint_str(output, color, status);
+    output.push_str(&line[end..]);
+}
+
+fn paint_columns(
+    output: &mut String,
+    line: &str,
+    name_width: usize,
+    ttl_color: Option<&str>,
+    cols: Columns,
+) {
+    let (name, rest) = split_at_chars(line, name_width);
+    let (ttl, rest) = split_at_chars(rest, cols.ttl);
+    let (rclass, rest) = split_at_chars(rest, cols.rclass);
+    let (rtype, rdata) = split_at_chars(rest, cols.rtype);
+
+    paint_str(output, CYAN, name);
+    match ttl_color {
+        Some(color) => paint_str(output, color, ttl),
+        None => output.push_str(ttl),
+    }
+    output.push_str(rclass);
+    paint_str(output, MAGENTA, rtype);
+    output.push_str(rdata);
+}
+
+/// Colors the text, leaving the padding around it as is.
+fn paint_str(output: &mut String, color: &str, s: &str) {
+    let text = s.trim();
+    if text.is_empty() {
+        output.push_str(s);
+        return;
+    }
+    let start = s.len() - s.trim_start().len();
+    let end = start + text.len();
+    let _ = write!(output, "{}{color}{text}{RESET}{}", &s[..start], &s[end..]);
+}
+
+/// Splits a string after `n` characters, the widths the columns are padded to.
+fn split_at_chars(s: &str, n: usize) -> (&str, &str) {
+    match s.char_indices().nth(n) {
+        Some((i, _)) => s.split_at(i),
+        None => (s, ""),
+    }
+}
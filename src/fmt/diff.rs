@@ -0,0 +1,460 @@
+use crate::{
+    args::DiffArgs,
+    fmt::{
+        normalize_name, question,
+        rdata::fmt_record_data_or_generic,
+        response_code,
+        save::{Capture, Settings},
+    },
+    serve::wire::flag_names,
+    status::Status,
+};
+use anyhow::{Context, Result, anyhow};
+use rsdns::{
+    message::{RecordsSection, reader::MessageReader},
+    names::InlineName,
+    records::Type,
+};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    net::SocketAddr,
+    str::FromStr,
+};
+
+/// Identifies the messages paired by the diff.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq, Hash)]
+struct Key {
+    qname: String,
+    qtype: String,
+    nameserver: Option<String>,
+}
+
+impl Key {
+    fn fmt(&self) -> String {
+        match self.nameserver {
+            Some(ref ns) => format!("{} {} @{ns}", self.qname, self.qtype),
+            None => format!("{} {}", self.qname, self.qtype),
+        }
+    }
+}
+
+/// The parts of a response compared by the diff.
+#[derive(Debug)]
+struct Digest {
+    rcode: String,
+    flags: String,
+    /// TTLs by section and record, the record in presentation format without the TTL.
+    records: BTreeMap<(u8, String), u32>,
+    /// The client settings the response was received with, repeated by `--rerun`.
+    settings: Settings,
+}
+
+#[derive(Debug, Serialize)]
+struct Change<T> {
+    a: T,
+    b: T,
+}
+
+#[derive(Debug, Serialize)]
+struct RecordChange {
+    section: &'static str,
+    record: String,
+}
+
+#[derive(Debug, Serialize)]
+struct TtlChange {
+    section: &'static str,
+    record: String,
+    a: u32,
+    b: u32,
+}
+
+#[derive(Debug, Serialize)]
+struct Difference {
+    #[serde(flatten)]
+    key: Key,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rcode: Option<Change<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    flags: Option<Change<String>>,
+    removed: Vec<RecordChange>,
+    added: Vec<RecordChange>,
+    ttl: Vec<TtlChange>,
+}
+
+#[derive(Debug, Serialize)]
+struct Report<'a> {
+    a: &'a str,
+    b: &'a str,
+    compared: usize,
+    changed: Vec<Difference>,
+    only_in_a: Vec<Key>,
+    only_in_b: Vec<Key>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    failed: Vec<Failure>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    unparsable: Vec<Unparsable>,
+}
+
+/// A question `--rerun` couldn't get a response to.
+#[derive(Debug, Serialize)]
+struct Failure {
+    #[serde(flatten)]
+    key: Key,
+    error: String,
+}
+
+/// A saved message that failed to parse, left out of the comparison.
+#[derive(Debug, Serialize)]
+struct Unparsable {
+    path: String,
+    /// The position of the message in the file, starting at 1.
+    message: usize,
+    error: String,
+}
+
+/// Compares the responses of two files saved with `--save`.
+///
+/// Messages are paired by query name, type and nameserver. If a file holds more than
+/// one response to a question, the last one is compared.
+pub fn run(args: &DiffArgs) -> Result<Status> {
+    let mut unparsable = Vec::new();
+    let (a_keys, a) = load(&args.a, &mut unparsable)?;
+    let (b_keys, b) = load(&args.b, &mut unparsable)?;
+
+    let mut report = Report {
+        a: &args.a,
+        b: &args.b,
+        compared: 0,
+        changed: Vec::new(),
+        only_in_a: Vec::new(),
+        only_in_b: Vec::new(),
+        failed: Vec::new(),
+        unparsable,
+    };
+
+    for key in a_keys.iter() {
+        let Some(db) = b.get(key) else {
+            report.only_in_a.push(key.clone());
+            continue;
+        };
+        report.add(key, &a[key], db);
+    }
+    report.only_in_b = b_keys.into_iter().filter(|k| !a.contains_key(k)).collect();
+
+    report.print(args.json)
+}
+
+/// A question recorded in a file, asked again by `--rerun`.
+#[derive(Debug)]
+pub struct Question {
+    pub qname: String,
+    pub qtype: Type,
+    /// The nameserver the question was asked, if known.
+    pub nameserver: Option<SocketAddr>,
+    /// The client settings the question was asked with.
+    pub settings: Settings,
+}
+
+/// Re-issues the questions of a file saved with `--save` and compares the new
+/// responses with the recorded ones.
+pub struct Rerun<'a> {
+    keys: Vec<Key>,
+    recorded: HashMap<Key, Digest>,
+    report: Report<'a>,
+}
+
+impl<'a> Rerun<'a> {
+    pub fn load(path: &'a str) -> Result<Rerun<'a>> {
+        let mut unparsable = Vec::new();
+        let (keys, recorded) = load(path, &mut unparsable)?;
+        Ok(Rerun {
+            keys,
+            recorded,
+            report: Report {
+                a: path,
+                b: "rerun",
+                compared: 0,
+                changed: Vec::new(),
+                only_in_a: Vec::new(),
+                only_in_b: Vec::new(),
+                failed: Vec::new(),
+                unparsable,
+            },
+        })
+    }
+
+    /// Returns the recorded questions.
+    pub fn questions(&self) -> Result<Vec<Question>> {
+        self.keys
+            .iter()
+            .map(|key| {
+                let qtype = Type::from_str(&key.qtype)
+                    .map_err(|_| anyhow!("{}: unknown query type: {}", self.report.a, key.qtype))?;
+                let ns = key.nameserver.as_deref().and_then(|ns| ns.parse().ok());
+                Ok(Question {
+                    qname: key.qname.clone(),
+                    qtype,
+                    nameserver: ns,
+                    settings: self.recorded[key].settings,
+                })
+            })
+            .collect()
+    }
+
+    /// Compares the new response to the i-th question with the recorded one.
+    pub fn add(&mut self, i: usize, msg: &[u8]) -> Result<()> {
+        let key = &self.keys[i];
+        let digest =
+            digest(msg, Settings::default()).with_context(|| format!("rerun of {}", key.fmt()))?;
+        self.report.add(key, &self.recorded[key], &digest);
+        Ok(())
+    }
+
+    /// Records the failure to get a new response to the i-th question.
+    pub fn add_error(&mut self, i: usize, err: &anyhow::Error) {
+        self.report.failed.push(Failure {
+            key: self.keys[i].clone(),
+            error: format!("{err:#}"),
+        });
+    }
+
+    /// Prints the report, returning [`Status::Differ`] if any response changed, or
+    /// [`Status::Parse`] if a recorded message failed to parse.
+    pub fn done(&self) -> Result<Status> {
+        self.report.print(false)
+    }
+}
+
+impl Report<'_> {
+    fn add(&mut self, key: &Key, a: &Digest, b: &Digest) {
+        self.compared += 1;
+        let diff = compare(key, a, b);
+        if diff.rcode.is_some()
+            || diff.flags.is_some()
+            || !diff.removed.is_empty()
+            || !diff.added.is_empty()
+            || !diff.ttl.is_empty()
+        {
+            self.changed.push(diff);
+        }
+    }
+
+    fn print(&self, json: bool) -> Result<Status> {
+        if json {
+            println!("{}", serde_json::to_string_pretty(self)?);
+        } else {
+            let mut buf = String::new();
+            fmt_report(&mut buf, self)?;
+            print!("{buf}");
+        }
+        let status = if self.unparsable.is_empty() {
+            Status::Success
+        } else {
+            Status::Parse
+        };
+        if self.changed.is_empty() && self.only_in_a.is_empty() && self.only_in_b.is_empty() {
+            Ok(status)
+        } else {
+            Ok(status.or(Status::Differ))
+        }
+    }
+}
+
+/// Loads the digests of a file, keeping the order the questions first appear in.
+///
+/// Messages failing to parse are added to `unparsable` and left out.
+fn load(path: &str, unparsable: &mut Vec<Unparsable>) -> Result<(Vec<Key>, HashMap<Key, Digest>)> {
+    let mut keys = Vec::new();
+    let mut digests = HashMap::new();
+
+    let captures = Capture::load_all(path)?;
+    let messages = captures
+        .iter()
+        .flat_map(|c| c.messages.iter().map(|em| (c.config.as_ref(), em)));
+    for (i, (config, em)) in messages.enumerate() {
+        let msg = em.msg();
+        let (qname, qtype) = question(&msg);
+        let qname = em
+            .qname()
+            .map(|n| n.to_string())
+            .or(qname)
+            .unwrap_or_default();
+        let key = Key {
+            qname: normalize_name(&qname),
+            qtype: em
+                .qtype()
+                .or(qtype)
+                .map_or_else(String::new, |t| t.to_string()),
+            nameserver: em.nameserver().map(|ns| ns.to_string()),
+        };
+        let settings = Settings::recorded(config, em.protocol());
+        let digest = match digest(&msg, settings) {
+            Ok(digest) => digest,
+            Err(e) => {
+                unparsable.push(Unparsable {
+                    path: path.to_string(),
+                    message: i + 1,
+                    error: format!("{e:#}"),
+                });
+                continue;
+            }
+        };
+        if digests.insert(key.clone(), digest).is_none() {
+            keys.push(key);
+        }
+    }
+    Ok((keys, digests))
+}
+
+fn digest(msg: &[u8], settings: Settings) -> Result<Digest> {
+    let rcode = response_code(msg)?.to_string();
+    let mut mr = MessageReader::new(msg)?;
+    let header = mr.header()?;
+    let raw = u16::from(header.flags);
+    let flags = flag_names(raw);
+
+    while mr.has_questions() {
+        mr.question()?;
+    }
+
+    let mut records = BTreeMap::new();
+    while mr.has_records() {
+        let rec_header = mr.record_header::<InlineName>()?;
+        let marker = rec_header.marker();
+        let section = rec_header.section();
+        if section == RecordsSection::Additional && rec_header.rtype() == Type::OPT {
+            mr.skip_record_data(marker)?;
+            continue;
+        }
+
+        let mut record = String::new();
+        write!(
+            &mut record,
+            "{} {} {} ",
+            rec_header.name().as_str().to_ascii_lowercase(),
+            rec_header.rclass(),
+            rec_header.rtype()
+        )?;
+        fmt_record_data_or_generic(&mut record, &mut mr, marker)?;
+        records.insert((section as u8, record), rec_header.ttl());
+    }
+
+    Ok(Digest {
+        rcode,
+        flags: flags.join(" "),
+        records,
+        settings,
+    })
+}
+
+fn section_name(section: u8) -> &'static str {
+    match section {
+        0 => "answer",
+        1 => "authority",
+        _ => "additional",
+    }
+}
+
+fn compare(key: &Key, a: &Digest, b: &Digest) -> Difference {
+    let change = |a: &String, b: &String| {
+        (a != b).then(|| Change {
+            a: a.clone(),
+            b: b.clone(),
+        })
+    };
+    let mut diff = Difference {
+        key: key.clone(),
+        rcode: change(&a.rcode, &b.rcode),
+        flags: change(&a.flags, &b.flags),
+        removed: Vec::new(),
+        added: Vec::new(),
+        ttl: Vec::new(),
+    };
+
+    for ((section, record), &ttl_a) in a.records.iter() {
+        match b.records.get(&(*section, record.clone())) {
+            None => diff.removed.push(RecordChange {
+                section: section_name(*section),
+                record: record.clone(),
+            }),
+            Some(&ttl_b) if ttl_b != ttl_a => diff.ttl.push(TtlChange {
+                section: section_name(*section),
+                record: record.clone(),
+                a: ttl_a,
+                b: ttl_b,
+            }),
+            Some(_) => {}
+        }
+    }
+    for (section, record) in b.records.keys() {
+        if !a.records.contains_key(&(*section, record.clone())) {
+            diff.added.push(RecordChange {
+                section: section_name(*section),
+                record: record.clone(),
+            });
+        }
+    }
+    diff
+}
+
+fn fmt_report<W: Write>(w: &mut W, report: &Report) -> Result<()> {
+    for diff in report.changed.iter() {
+        writeln!(w, "{}", diff.key.fmt())?;
+        if let Some(ref c) = diff.rcode {
+            writeln!(w, "    rcode: {} -> {}", c.a, c.b)?;
+        }
+        if let Some(ref c) = diff.flags {
+            writeln!(w, "    flags: {} -> {}", c.a, c.b)?;
+        }
+        for r in diff.removed.iter() {
+            writeln!(w, "    - {}: {}", r.section, r.record)?;
+        }
+        for r in diff.added.iter() {
+            writeln!(w, "    + {}: {}", r.section, r.record)?;
+        }
+        for t in diff.ttl.iter() {
+            writeln!(
+                w,
+                "    ~ {}: {} ttl: {} -> {}",
+                t.section, t.record, t.a, t.b
+            )?;
+        }
+    }
+    for key in report.only_in_a.iter() {
+        writeln!(w, "- only in {}: {}", report.a, key.fmt())?;
+    }
+    for key in report.only_in_b.iter() {
+        writeln!(w, "+ only in {}: {}", report.b, key.fmt())?;
+    }
+    for f in report.failed.iter() {
+        writeln!(w, "! failed: {}: {}", f.key.fmt(), f.error)?;
+    }
+    for u in report.unparsable.iter() {
+        writeln!(
+            w,
+            "! unparsable: {}: message {}: {}",
+            u.path, u.message, u.error
+        )?;
+    }
+    write!(
+        w,
+        ";; {} questions compared: {} changed, {} only in {}, {} only in {}",
+        report.compared,
+        report.changed.len(),
+        report.only_in_a.len(),
+        report.a,
+        report.only_in_b.len(),
+        report.b
+    )?;
+    if !report.failed.is_empty() {
+        write!(w, ", {} failed", report.failed.len())?;
+    }
+    if !report.unparsable.is_empty() {
+        write!(w, ", {} unparsable", report.unparsable.len())?;
+    }
+    writeln!(w)?;
+    Ok(())
+}
@@ -0,0 +1,395 @@
+use crate::{
+    fmt::rdata::character_string,
+    serve::{name::escape, wire::flag_names},
+};
+use anyhow::{Result, bail};
+use rsdns::{
+    message::{OpCode, RCode},
+    records::{Class, Type},
+};
+use std::{
+    fmt::Write,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+/// The number of bytes shown on a line.
+const CHUNK_LEN: usize = 8;
+
+/// The longest chain of compression pointers followed before giving up.
+const MAX_POINTERS: usize = 64;
+
+/// Formats a message annotating the meaning of every byte range, like the packet tree
+/// of Wireshark.
+///
+/// The message is read field by field without rsdns, so a malformed message is shown
+/// up to the offset where it breaks, followed by the bytes left unparsed.
+pub fn fmt<W: Write>(w: &mut W, title: &str, msg: &[u8]) -> Result<()> {
+    writeln!(w, ";; {title}: {} bytes", msg.len())?;
+    let mut d = Dissector::new(w, msg);
+    match d.message() {
+        Ok(()) if d.pos < msg.len() => {
+            let start = d.pos;
+            d.pos = msg.len();
+            d.line(start, 0, "!! trailing data")?;
+        }
+        Ok(()) => {}
+        Err(e) => {
+            writeln!(d.w, ";; !! {e}")?;
+            if d.pos < msg.len() {
+                let start = d.pos;
+                d.pos = msg.len();
+                d.line(start, 0, "!! unparsed")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Locates the failure to parse the n-th question or record of a message, counting
+/// from zero.
+///
+/// Returns the offset of the item, or of the item where the message breaks if it
+/// breaks before, and the offset where the message breaks, if it does within that
+/// item. An item rsdns rejects but which is well-formed, e.g. a name with other
+/// characters than letters, digits, `-` and `_`, doesn't break the message.
+pub fn failure_offsets(msg: &[u8], n: usize) -> (usize, Option<usize>) {
+    let mut sink = String::new();
+    let mut d = Dissector::new(&mut sink, msg);
+    // on failure, the position is where the message breaks
+    let broken = d.message().is_err();
+    match d.items.get(n) {
+        Some(&start) if d.items.len() > n + 1 || !broken => (start, None),
+        Some(&start) => (start, Some(d.pos)),
+        None => (d.items.last().copied().unwrap_or(0), Some(d.pos)),
+    }
+}
+
+struct Dissector<'a, W: Write> {
+    w: &'a mut W,
+    msg: &'a [u8],
+    pos: usize,
+    /// The end of the data being read: of the record data, or of the message.
+    limit: usize,
+    /// The offsets of the questions and records read so far.
+    items: Vec<usize>,
+}
+
+impl<'a, W: Write> Dissector<'a, W> {
+    fn new(w: &'a mut W, msg: &'a [u8]) -> Self {
+        Dissector {
+            w,
+            msg,
+            pos: 0,
+            limit: msg.len(),
+            items: Vec::new(),
+        }
+    }
+
+    fn message(&mut self) -> Result<()> {
+        self.heading(";; HEADER")?;
+        let id = self.u16("id")?;
+        self.line(self.pos - 2, 1, &format!("id: {id}"))?;
+
+        let flags = self.u16("flags")?;
+        let names = flag_names(flags);
+        self.line(
+            self.pos - 2,
+            1,
+            &format!(
+                "flags: {flags:#06x} [{}] opcode: {}, rcode: {}{}",
+                names.join(" "),
+                OpCode::from(((flags >> 11) & 0x0F) as u8),
+                RCode::from(flags & 0x0F),
+                if flags & 0x0040 != 0 { ", z: 1" } else { "" },
+            ),
+        )?;
+
+        let mut counts = [0u16; 4];
+        for (i, name) in ["qdcount", "ancount", "nscount", "arcount"]
+            .iter()
+            .enumerate()
+        {
+            counts[i] = self.u16(name)?;
+            self.line(self.pos - 2, 1, &format!("{name}: {}", counts[i]))?;
+        }
+
+        self.heading(";; QUESTION SECTION")?;
+        for _ in 0..counts[0] {
+            self.items.push(self.pos);
+            self.name(1, "qname")?;
+            let qtype = Type::from(self.u16("qtype")?);
+            self.line(self.pos - 2, 1, &format!("qtype: {qtype}"))?;
+            let qclass = Class::from(self.u16("qclass")?);
+            self.line(self.pos - 2, 1, &format!("qclass: {qclass}"))?;
+        }
+
+        for (i, section) in ["ANSWER", "AUTHORITY", "ADDITIONAL"].iter().enumerate() {
+            self.heading(&format!(";; {section} SECTION"))?;
+            for _ in 0..counts[i + 1] {
+                self.record()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn record(&mut self) -> Result<()> {
+        let start = self.pos;
+        self.items.push(start);
+        self.name(1, "name")?;
+        let rtype = Type::from(self.u16("type")?);
+        self.line(self.pos - 2, 1, &format!("type: {rtype}"))?;
+
+        let class = self.u16("class")?;
+        if rtype == Type::OPT {
+            self.line(self.pos - 2, 1, &format!("udp payload size: {class}"))?;
+        } else {
+            self.line(self.pos - 2, 1, &format!("class: {}", Class::from(class)))?;
+        }
+        let ttl = self.u32("ttl")?;
+        if rtype == Type::OPT {
+            self.line(
+                self.pos - 4,
+                1,
+                &format!(
+                    "extended rcode: {}, version: {}, flags: {:#06x}{}",
+                    ttl >> 24,
+                    (ttl >> 16) & 0xFF,
+                    ttl & 0xFFFF,
+                    if ttl & 0x8000 != 0 { " [do]" } else { "" },
+                ),
+            )?;
+        } else {
+            self.line(self.pos - 4, 1, &format!("ttl: {ttl}"))?;
+        }
+
+        let rdlen = self.u16("rdlength")? as usize;
+        self.line(self.pos - 2, 1, &format!("rdlength: {rdlen}"))?;
+        if self.pos + rdlen > self.limit {
+            bail!(
+                "{:04x}: the record data of the record at {start:04x} needs {rdlen} bytes, {} left",
+                self.pos,
+                self.limit - self.pos
+            );
+        }
+
+        let end = self.pos + rdlen;
+        self.limit = end;
+        let res = self.rdata(rtype);
+        self.limit = self.msg.len();
+        res?;
+        if self.pos < end {
+            let start = self.pos;
+            self.pos = end;
+            self.line(start, 2, "!! record data left unparsed")?;
+        }
+        Ok(())
+    }
+
+    fn rdata(&mut self, rtype: Type) -> Result<()> {
+        let len = self.limit - self.pos;
+        match rtype {
+            Type::A => {
+                let b = self.take(4, "address")?;
+                let addr = Ipv4Addr::new(b[0], b[1], b[2], b[3]);
+                self.line(self.pos - 4, 2, &format!("address: {addr}"))?;
+            }
+            Type::AAAA => {
+                let b: [u8; 16] = self.take(16, "address")?.try_into()?;
+                self.line(self.pos - 16, 2, &format!("address: {}", Ipv6Addr::from(b)))?;
+            }
+            Type::NS | Type::CNAME | Type::PTR => {
+                self.name(2, &rtype.to_string().to_lowercase())?;
+            }
+            Type::MX => {
+                self.number16("preference")?;
+                self.name(2, "exchange")?;
+            }
+            Type::SRV => {
+                for field in ["priority", "weight", "port"] {
+                    self.number16(field)?;
+                }
+                self.name(2, "target")?;
+            }
+            Type::SOA => {
+                self.name(2, "mname")?;
+                self.name(2, "rname")?;
+                for field in ["serial", "refresh", "retry", "expire", "minimum"] {
+                    let v = self.u32(field)?;
+                    self.line(self.pos - 4, 2, &format!("{field}: {v}"))?;
+                }
+            }
+            Type::TXT | Type::HINFO => {
+                while self.pos < self.limit {
+                    self.char_string()?;
+                }
+            }
+            Type::OPT => {
+                while self.pos < self.limit {
+                    self.option()?;
+                }
+            }
+            _ if len > 0 => {
+                self.take(len, "rdata")?;
+                self.line(self.pos - len, 2, &format!("rdata: {len} bytes"))?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn option(&mut self) -> Result<()> {
+        let start = self.pos;
+        let code = self.u16("option code")?;
+        let len = self.u16("option length")? as usize;
+        let name = match code {
+            3 => "NSID",
+            8 => "CLIENT-SUBNET",
+            10 => "COOKIE",
+            11 => "TCP-KEEPALIVE",
+            12 => "PADDING",
+            15 => "EDE",
+            _ => "",
+        };
+        self.line(start, 2, &format!("option: {name}({code}), length: {len}"))?;
+        if len > 0 {
+            let data = self.take(len, "option data")?;
+            let hex: String = data.iter().map(|b| format!("{b:02x}")).collect();
+            self.line(self.pos - len, 3, &format!("data: {hex}"))?;
+        }
+        Ok(())
+    }
+
+    fn char_string(&mut self) -> Result<()> {
+        let len = self.take(1, "string length")?[0] as usize;
+        let text = self.take(len, "string")?;
+        let text = character_string(text);
+        self.line(self.pos - len - 1, 2, &format!("string: {text}"))
+    }
+
+    fn number16(&mut self, field: &str) -> Result<()> {
+        let v = self.u16(field)?;
+        self.line(self.pos - 2, 2, &format!("{field}: {v}"))
+    }
+
+    /// Reads a domain name, following compression pointers.
+    fn name(&mut self, depth: usize, field: &str) -> Result<()> {
+        let start = self.pos;
+        let (name, pointer) = self.read_name()?;
+        let text = match pointer {
+            Some(p) => format!("{field}: {name} (pointer to {p:04x})"),
+            None => format!("{field}: {name}"),
+        };
+        self.line(start, depth, &text)
+    }
+
+    /// Returns the name at the current position and the first compression pointer,
+    /// if any. Only the bytes up to and including the pointer are consumed.
+    fn read_name(&mut self) -> Result<(String, Option<usize>)> {
+        let mut name = String::new();
+        let mut first_pointer = None;
+        let mut pos = self.pos;
+        let mut pointers = 0;
+        loop {
+            let Some(&len) = self.msg.get(pos) else {
+                bail!("{pos:04x}: the name at {:04x} runs past the end", self.pos);
+            };
+            match len & 0xC0 {
+                0x00 if len == 0 => {
+                    pos += 1;
+                    break;
+                }
+                0x00 => {
+                    let label = pos + 1..pos + 1 + len as usize;
+                    let Some(label) = self.msg.get(label) else {
+                        bail!("{pos:04x}: label of {len} bytes runs past the end");
+                    };
+                    name.push_str(&escape(label));
+                    name.push('.');
+                    pos += 1 + len as usize;
+                }
+                0xC0 => {
+                    let Some(&low) = self.msg.get(pos + 1) else {
+                        bail!("{pos:04x}: compression pointer runs past the end");
+                    };
+                    let target = ((len as usize & 0x3F) << 8) | low as usize;
+                    pointers += 1;
+                    if pointers > MAX_POINTERS || target >= pos {
+                        bail!("{pos:04x}: bad compression pointer to {target:04x}");
+                    }
+                    if first_pointer.is_none() {
+                        first_pointer = Some(target);
+                        self.advance(pos + 2, "name")?;
+                    }
+                    pos = target;
+                }
+                _ => bail!("{pos:04x}: bad label type {len:#04x}"),
+            }
+        }
+        if first_pointer.is_none() {
+            self.advance(pos, "name")?;
+        }
+        if name.is_empty() {
+            name.push('.');
+        }
+        Ok((name, first_pointer))
+    }
+
+    fn advance(&mut self, pos: usize, what: &str) -> Result<()> {
+        if pos > self.limit {
+            bail!("{:04x}: {what} runs past the record data", self.pos);
+        }
+        self.pos = pos;
+        Ok(())
+    }
+
+    fn take(&mut self, len: usize, what: &str) -> Result<&'a [u8]> {
+        if self.pos + len > self.limit {
+            bail!(
+                "{:04x}: {what} needs {len} bytes, {} left",
+                self.pos,
+                self.limit - self.pos
+            );
+        }
+        self.pos += len;
+        Ok(&self.msg[self.pos - len..self.pos])
+    }
+
+    fn u16(&mut self, what: &str) -> Result<u16> {
+        let b = self.take(2, what)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self, what: &str) -> Result<u32> {
+        let b = self.take(4, what)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn heading(&mut self, text: &str) -> Result<()> {
+        writeln!(self.w, "{text}")?;
+        Ok(())
+    }
+
+    /// Writes the bytes from `start` to the current position, annotated with `text`.
+    ///
+    /// Bytes that don't fit the first line continue on the following lines.
+    fn line(&mut self, start: usize, depth: usize, text: &str) -> Result<()> {
+        let bytes = &self.msg[start..self.pos];
+        for (i, chunk) in bytes.chunks(CHUNK_LEN).enumerate() {
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{b:02x}")).collect();
+            let offset = start + i * CHUNK_LEN;
+            if i == 0 {
+                let indent = (depth.max(1) - 1) * 2;
+                writeln!(
+                    self.w,
+                    "{offset:04x}  {:width$}  {:indent$}{text}",
+                    hex.join(" "),
+                    "",
+                    width = CHUNK_LEN * 3 - 1
+                )?;
+            } else {
+                writeln!(self.w, "{offset:04x}  {}", hex.join(" "))?;
+            }
+        }
+        Ok(())
+    }
+}
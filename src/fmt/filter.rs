@@ -0,0 +1,78 @@
+use crate::{
+    args::Args,
+    fmt::{Exchange, normalize_name, response_code},
+};
+use std::time::Duration;
+
+/// Checks if a message read from a file passes the `--read` filters.
+///
+/// Messages lacking the data a filter needs, e.g. a timestamp, don't pass it.
+pub fn matches(args: &Args, ex: &Exchange) -> bool {
+    if let Some(ref glob) = args.filter_qname
+        && !ex.qname.is_some_and(|qname| glob_match(glob, qname))
+    {
+        return false;
+    }
+    if !args.filter_qtype.is_empty() && !ex.qtype.is_some_and(|t| args.filter_qtype.contains(&t)) {
+        return false;
+    }
+    if !args.filter_rcode.is_empty()
+        && !response_code(ex.msg).is_ok_and(|rc| args.filter_rcode.contains(&rc))
+    {
+        return false;
+    }
+    if !args.filter_server.is_empty()
+        && !ex.ns.is_some_and(|ns| {
+            args.filter_server
+                .iter()
+                .any(|s| s.ip() == ns.ip() && (s.port() == 0 || s.port() == ns.port()))
+        })
+    {
+        return false;
+    }
+    if let Some(since) = args.since
+        && ex.ts.is_none_or(|ts| ts < since)
+    {
+        return false;
+    }
+    if let Some(until) = args.until
+        && ex.ts.is_none_or(|ts| ts >= until)
+    {
+        return false;
+    }
+    if let Some(min) = args.min_latency
+        && ex.elapsed.is_none_or(|e| e < Duration::from_millis(min))
+    {
+        return false;
+    }
+    true
+}
+
+/// Matches a domain name against a pattern with `*` and `?` wildcards.
+///
+/// The comparison is ASCII case-insensitive and ignores a trailing dot.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let p = normalize_name(pattern);
+    let n = normalize_name(name);
+    let (p, n) = (p.as_bytes(), n.as_bytes());
+
+    let (mut pi, mut ni) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while ni < n.len() {
+        if pi < p.len() && (p[pi] == b'?' || p[pi] == n[ni]) {
+            pi += 1;
+            ni += 1;
+        } else if pi < p.len() && p[pi] == b'*' {
+            star = Some((pi, ni));
+            pi += 1;
+        } else if let Some((sp, sn)) = star {
+            // let the last star match one more character
+            pi = sp + 1;
+            ni = sn + 1;
+            star = Some((sp, sn + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == b'*')
+}
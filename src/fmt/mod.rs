@@ -1,8 +1,18 @@
+mod color;
+pub mod diff;
+mod dissect;
+mod filter;
+mod multiline;
+mod pcap;
+mod propagation;
 mod rdata;
 mod rrset;
 mod rust;
-mod save;
+pub mod save;
+mod summary;
+mod watch;
 mod zone;
+mod zonefile;
 
 //
 // ----------------------------------------------------------------------
@@ -10,23 +20,96 @@ mod zone;
 
 use crate::{
     args::{Args, OutputFormat},
-    fmt::save::EncodedMessage,
+    fmt::save::{Capture, EncodedMessage, EncodedProtocol, Writer},
+    status::Status,
 };
 use anyhow::{Result, bail};
 use rdata::{RDataFmt, RDataFormatter};
-use rsdns::records::{
-    RecordSet, Type,
-    data::{self, RData},
+use rsdns::{
+    message::{RCode, reader::MessageReader},
+    records::{
+        RecordSet, Type,
+        data::{self, RData},
+    },
 };
 use std::{
+    collections::HashMap,
     net::SocketAddr,
     time::{Duration, SystemTime},
 };
 
+/// A response, the query it answers, and the circumstances it was received in.
+#[derive(Debug, Clone, Copy)]
+pub struct Exchange<'a> {
+    pub qname: Option<&'a str>,
+    pub qtype: Option<Type>,
+    pub query: Option<&'a [u8]>,
+    /// Whether the query was rebuilt from the client configuration rather than captured.
+    pub query_reconstructed: bool,
+    pub msg: &'a [u8],
+    pub ns: Option<SocketAddr>,
+    pub ts: Option<SystemTime>,
+    pub elapsed: Option<Duration>,
+    pub protocol: Option<&'a EncodedProtocol>,
+    /// The command line of the run that saved the message, when read from a file.
+    pub cmd_line: Option<&'a str>,
+}
+
+/// Returns the query name and type of a message.
+pub fn question(msg: &[u8]) -> (Option<String>, Option<Type>) {
+    if let Ok(mut mr) = MessageReader::new(msg)
+        && mr.header().is_ok()
+        && let Ok(q) = mr.the_question()
+    {
+        let qname = q.qname.as_str();
+        let qname = qname
+            .strip_suffix('.')
+            .filter(|n| !n.is_empty())
+            .unwrap_or(qname);
+        return (Some(qname.to_string()), Some(q.qtype));
+    }
+    (None, None)
+}
+
+/// Normalizes a domain name for comparison: lowercase and without the trailing dot.
+pub fn normalize_name(name: &str) -> String {
+    let name = name.to_ascii_lowercase();
+    match name.strip_suffix('.') {
+        Some(n) if !n.is_empty() => n.to_string(),
+        _ => name,
+    }
+}
+
+/// Marks a parse error printed already, along with the partial output of `+besteffort`.
+#[derive(Debug)]
+pub struct Reported;
+
+impl std::fmt::Display for Reported {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("reported")
+    }
+}
+
+/// Checks if the error of a message was printed already, to print it only once.
+pub fn is_reported(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<Reported>().is_some()
+}
+
+/// Returns the response code of a message, including the EDNS extension.
+pub fn response_code(msg: &[u8]) -> Result<RCode> {
+    Ok(summary::Summary::from_msg(msg)?.rcode)
+}
+
 pub struct Format<'a> {
     args: &'a Args,
     cnt: usize,
-    json: Vec<serde_json::Value>,
+    saver: Option<Writer>,
+    pcap: Option<pcap::Writer>,
+    watch: watch::Watch,
+    report: propagation::Report,
+    zonefile: zonefile::ZoneFile,
+    /// The number of messages read from files that failed to parse.
+    failed: usize,
 }
 
 impl<'a> Format<'a> {
@@ -34,59 +117,251 @@ impl<'a> Format<'a> {
         Self {
             args,
             cnt: 0,
-            json: Vec::new(),
+            saver: None,
+            pcap: None,
+            watch: watch::Watch::new(),
+            report: propagation::Report::new(),
+            zonefile: zonefile::ZoneFile::new(),
+            failed: 0,
         }
     }
 
-    pub fn add(
-        &mut self,
-        qname: Option<&str>,
-        qtype: Option<Type>,
-        msg: &[u8],
-        ns: Option<SocketAddr>,
-        ts: Option<SystemTime>,
-        elapsed: Option<Duration>,
-    ) -> Result<()> {
-        match self.args.format {
-            OutputFormat::Short => self.short(msg)?,
-            OutputFormat::Zone | OutputFormat::ZoneRfc3597 => self.zone(msg, ns, ts, elapsed)?,
-            OutputFormat::Rust => self.rust(qname, qtype, msg)?,
-        };
+    pub fn add(&mut self, ex: &Exchange) -> Result<()> {
+        if self.args.summary {
+            self.summary(ex)?;
+        } else if self.args.propagation {
+            self.propagation(ex)?;
+        } else if self.args.is_watch() {
+            self.watch(ex)?;
+        } else {
+            self.print(ex)?;
+        }
         if self.args.has_save_path() {
-            self.json
-                .push(EncodedMessage::encode(msg, qname, qtype, ns, ts, elapsed)?);
+            self.save(ex)?;
+        }
+        if let Some(ref path) = self.args.save_pcap {
+            let pcap = match self.pcap {
+                Some(ref mut pcap) => pcap,
+                None => self.pcap.insert(pcap::Writer::create(path)?),
+            };
+            pcap.write(ex)?;
         }
         self.cnt += 1;
         Ok(())
     }
 
+    /// Reports a failed query.
+    pub fn add_error(
+        &mut self,
+        qname: &str,
+        qtype: Type,
+        ns: SocketAddr,
+        ts: SystemTime,
+        err: &anyhow::Error,
+    ) -> Result<()> {
+        let key = format!("{qname} {qtype}");
+        let mut buf = String::new();
+        if self.args.propagation {
+            self.report.begin(&mut buf, &key, &self.args.expect)?;
+            let resolver = self.args.resolvers.iter().find(|r| r.addr == ns);
+            self.report
+                .fmt_error_line(&mut buf, Some(ns), resolver, err)?;
+        } else {
+            let iteration = self.watch.fail(&key);
+            watch::fmt_error_line(&mut buf, iteration, &key, Some(ts), err)?;
+        }
+        println!("{buf}");
+        Ok(())
+    }
+
     pub fn done(&mut self) -> Result<()> {
-        if self.args.has_save_path() && !self.json.is_empty() {
-            return EncodedMessage::save_all(&self.json, self.args.save_path.as_ref().unwrap());
+        if self.args.propagation {
+            let mut buf = String::new();
+            self.report.end(&mut buf)?;
+            print!("{buf}");
+        }
+        if self.args.format == OutputFormat::ZoneFile {
+            let mut buf = String::new();
+            self.zonefile.fmt(&mut buf)?;
+            print!("{buf}");
         }
         Ok(())
     }
 
-    pub fn read(&mut self) -> Result<()> {
+    /// Writes a message to the `--save` file, which is created with the first message.
+    fn save(&mut self, ex: &Exchange) -> Result<()> {
+        let saver = match self.saver {
+            Some(ref mut saver) => saver,
+            None => self.saver.insert(Writer::open(
+                self.args,
+                self.args.save_path.as_ref().unwrap(),
+                self.args.save_append,
+            )?),
+        };
+        saver.write(&EncodedMessage::encode(ex))
+    }
+
+    pub fn read(&mut self) -> Result<Status> {
         let read_path = self.args.read_path.as_ref().unwrap();
-        let responses = EncodedMessage::load_all(read_path)?;
+        let captures = Capture::load_all(read_path)?;
+        let total = captures.iter().map(|c| c.messages.len()).sum();
+
+        for (c, r) in captures
+            .iter()
+            .flat_map(|c| c.messages.iter().map(move |r| (c, r)))
+        {
+            let msg = r.msg();
+            let query = r.query();
+            self.replay(&Exchange {
+                qname: r.qname(),
+                qtype: r.qtype(),
+                query: query.as_deref(),
+                query_reconstructed: r.query_reconstructed,
+                msg: &msg,
+                ns: r.nameserver(),
+                ts: r.time(),
+                elapsed: r.elapsed(),
+                protocol: r.protocol(),
+                cmd_line: c.cmd_line.as_deref(),
+            })?;
+        }
+
+        Ok(self.read_done(total))
+    }
+
+    /// Feeds the responses found in a capture to the output, along with their queries.
+    pub fn read_pcap(&mut self) -> Result<Status> {
+        let path = self.args.read_pcap.as_ref().unwrap();
+        let messages = pcap::load_all(path, self.args.port)?;
+
+        let mut total = 0;
+        let mut queries = HashMap::new();
+        for m in messages {
+            if !m.is_response() {
+                queries.insert((m.src, m.dst, m.id()), m);
+                continue;
+            }
+
+            total += 1;
+            let query = queries.remove(&(m.dst, m.src, m.id()));
+            let (qname, qtype) = question(&m.msg);
+            let protocol = pcap::protocol(query.as_ref(), &m);
+            self.replay(&Exchange {
+                qname: qname.as_deref(),
+                qtype,
+                query: query.as_ref().map(|q| q.msg.as_slice()),
+                query_reconstructed: false,
+                msg: &m.msg,
+                ns: Some(m.src),
+                ts: Some(query.as_ref().map_or(m.ts, |q| q.ts)),
+                elapsed: query.as_ref().and_then(|q| m.ts.duration_since(q.ts).ok()),
+                protocol: Some(&protocol),
+                cmd_line: None,
+            })?;
+        }
+
+        Ok(self.read_done(total))
+    }
+
+    /// Passes a message read from a file to the output, unless it is filtered out.
+    /// A message failing to parse is reported and skipped.
+    fn replay(&mut self, ex: &Exchange) -> Result<()> {
+        if !filter::matches(self.args, ex) {
+            return Ok(());
+        }
+        match self.add(ex) {
+            Err(e) if Status::from_error(&e) == Status::Parse => {
+                match ex.ns {
+                    _ if is_reported(&e) => {}
+                    Some(ns) => println!(";; failed to parse the response from {ns}: {e:#}"),
+                    None => println!(";; failed to parse the response: {e:#}"),
+                }
+                self.failed += 1;
+                Ok(())
+            }
+            res => res,
+        }
+    }
 
-        for r in responses {
-            self.add(
-                r.qname(),
-                r.qtype(),
-                &r.msg(),
-                r.nameserver(),
-                r.time(),
-                r.elapsed(),
-            )?;
+    fn read_done(&self, total: usize) -> Status {
+        if self.args.summary {
+            println!(";; {} of {total} messages", self.cnt);
         }
+        if self.failed > 0 {
+            Status::Parse
+        } else {
+            Status::Success
+        }
+    }
 
+    fn print(&mut self, ex: &Exchange) -> Result<()> {
+        match self.args.format {
+            OutputFormat::Short => self.short(ex),
+            OutputFormat::Zone | OutputFormat::ZoneRfc3597 => self.zone(ex),
+            OutputFormat::Rust | OutputFormat::RustTest => self.array(ex, rust::Lang::Rust),
+            OutputFormat::CBytes => self.array(ex, rust::Lang::C),
+            OutputFormat::Python => self.array(ex, rust::Lang::Python),
+            OutputFormat::Go => self.array(ex, rust::Lang::Go),
+            OutputFormat::Hex => self.encoded(ex, rust::fmt_hex),
+            OutputFormat::Xxd => self.encoded(ex, rust::fmt_xxd),
+            OutputFormat::Base64 => self.encoded(ex, rust::fmt_base64),
+            OutputFormat::ZoneFile => self.zonefile.add(ex.msg),
+            OutputFormat::Dissect => self.dissect(ex),
+        }
+    }
+
+    /// Prints a line with the digest of a message.
+    fn summary(&mut self, ex: &Exchange) -> Result<()> {
+        let summary = summary::Summary::from_msg(ex.msg)?;
+        let mut buf = String::new();
+        summary::fmt_line(&mut buf, ex, &summary)?;
+        println!("{buf}");
         Ok(())
     }
 
-    fn short(&self, msg: &[u8]) -> Result<()> {
-        let qtype = self.args.qtype();
+    fn propagation(&mut self, ex: &Exchange) -> Result<()> {
+        let key = format!("{} {}", ex.qname.unwrap_or_default(), self.qtype(ex));
+        let summary = summary::Summary::from_msg(ex.msg)?;
+        let resolver = self.args.resolvers.iter().find(|r| Some(r.addr) == ex.ns);
+
+        let mut buf = String::new();
+        self.report.begin(&mut buf, &key, &self.args.expect)?;
+        self.report
+            .fmt_line(&mut buf, ex.ns, resolver, &summary, &self.args.expect)?;
+        println!("{buf}");
+        Ok(())
+    }
+
+    fn watch(&mut self, ex: &Exchange) -> Result<()> {
+        let key = format!("{} {}", ex.qname.unwrap_or_default(), self.qtype(ex));
+        let summary = summary::Summary::from_msg(ex.msg)?;
+        let (iteration, change) = self.watch.update(&key, summary.clone());
+
+        let mut buf = String::new();
+        watch::fmt_line(
+            &mut buf, iteration, &key, &summary, ex.ts, ex.elapsed, change,
+        )?;
+        println!("{buf}");
+
+        if change != watch::Change::Same {
+            println!();
+            self.print(ex)?;
+            println!();
+        }
+        Ok(())
+    }
+
+    /// Returns the query type of a message: the saved one, the one in the question,
+    /// or the one given on the command line.
+    fn qtype(&self, ex: &Exchange) -> Type {
+        ex.qtype
+            .or_else(|| question(ex.msg).1)
+            .unwrap_or_else(|| self.args.qtype())
+    }
+
+    fn short(&self, ex: &Exchange) -> Result<()> {
+        let msg = ex.msg;
+        let qtype = self.qtype(ex);
         match qtype {
             Type::A => Self::short_rrset::<data::A>(msg),
             Type::NS => Self::short_rrset::<data::Ns>(msg),
@@ -103,7 +378,7 @@ impl<'a> Format<'a> {
             Type::HINFO => Self::short_rrset::<data::Hinfo>(msg),
             Type::MINFO => Self::short_rrset::<data::Minfo>(msg),
             Type::MX => Self::short_rrset::<data::Mx>(msg),
-            Type::TXT => Self::short_rrset::<data::Txt>(msg),
+            Type::TXT => Self::short_txt(msg),
             Type::AAAA => Self::short_rrset::<data::Aaaa>(msg),
             Type::SRV => Self::short_rrset::<data::Srv>(msg),
             t if t.is_meta_type() => {
@@ -126,24 +401,73 @@ impl<'a> Format<'a> {
         Ok(())
     }
 
-    fn zone(
-        &self,
-        msg: &[u8],
-        ns: Option<SocketAddr>,
-        ts: Option<SystemTime>,
-        elapsed: Option<Duration>,
-    ) -> Result<()> {
-        if self.cnt > 0 {
+    fn short_txt(msg: &[u8]) -> Result<()> {
+        let rr_set = RecordSet::<data::Txt>::from_msg(msg)?;
+        let mut buf = String::new();
+        rrset::fmt_short_txt(&mut buf, msg, &rr_set)?;
+        print!("{buf}");
+        Ok(())
+    }
+
+    fn zone(&self, ex: &Exchange) -> Result<()> {
+        if self.cnt > 0 && !self.args.is_watch() && self.args.display.comments {
+            println!();
+        }
+        let query = match ex.query {
+            Some(query) if self.args.qr => Some(zone::Output::new(
+                self.args,
+                &Exchange {
+                    msg: query,
+                    ns: None,
+                    ts: None,
+                    elapsed: None,
+                    ..*ex
+                },
+            )?),
+            _ => None,
+        };
+        zone::Output::new(self.args, ex)?.print(query.as_ref())
+    }
+
+    fn dissect(&self, ex: &Exchange) -> Result<()> {
+        if self.cnt > 0 && !self.args.is_watch() {
             println!();
         }
-        zone::Output::new(self.args, msg, ns, ts, elapsed)?.print()
+        let mut buf = String::new();
+        if let Some(query) = ex.query
+            && self.args.qr
+        {
+            dissect::fmt(&mut buf, "QUERY", query)?;
+            buf.push('\n');
+        }
+        dissect::fmt(&mut buf, "RESPONSE", ex.msg)?;
+        print!("{buf}");
+        Ok(())
     }
 
-    fn rust(&self, qname: Option<&str>, qtype: Option<Type>, msg: &[u8]) -> Result<()> {
+    fn array(&self, ex: &Exchange, lang: rust::Lang) -> Result<()> {
         let name = format!("M{}", self.cnt);
         let mut buf = String::new();
-        rust::fmt(&mut buf, qtype, qname, &name, msg)?;
+        rust::fmt(
+            &mut buf,
+            lang,
+            Some(self.qtype(ex)),
+            ex.qname,
+            &name,
+            ex.msg,
+        )?;
+        if self.args.format == OutputFormat::RustTest {
+            buf.push('\n');
+            rust::fmt_test(&mut buf, &name, ex.msg)?;
+        }
         println!("{buf}");
         Ok(())
     }
+
+    fn encoded(&self, ex: &Exchange, f: fn(&mut String, &[u8]) -> Result<()>) -> Result<()> {
+        let mut buf = String::new();
+        f(&mut buf, ex.msg)?;
+        print!("{buf}");
+        Ok(())
+    }
 }
@@ -0,0 +1,241 @@
+use crate::{
+    fmt::rdata::{character_string, character_strings},
+    serve::name::escape,
+};
+use anyhow::{Result, bail};
+use base64::{Engine as _, engine::general_purpose::STANDARD as Base64Engine};
+use chrono::DateTime;
+use rsdns::{
+    message::reader::{MessageReader, RecordMarker},
+    records::{Type, data},
+};
+use std::fmt::Write;
+
+// the DNSSEC types rsdns has no constants for
+const DNSKEY: u16 = 48;
+const RRSIG: u16 = 46;
+
+/// The length of the lines base64 keys and signatures are split into.
+const BASE64_LINE_LEN: usize = 44;
+
+const UNITS: [(u32, &str, &str); 5] = [
+    (7 * 86400, "w", "week"),
+    (86400, "d", "day"),
+    (3600, "h", "hour"),
+    (60, "m", "minute"),
+    (1, "s", "second"),
+];
+
+/// Formats a TTL with units, like `1h30m`.
+pub fn ttl_units(ttl: u32) -> String {
+    if ttl == 0 {
+        return "0s".to_string();
+    }
+    let mut s = String::new();
+    let mut left = ttl;
+    for (secs, unit, _) in UNITS {
+        if left >= secs {
+            let _ = write!(s, "{}{unit}", left / secs);
+            left %= secs;
+        }
+    }
+    s
+}
+
+/// Formats a duration in words, like `1 hour 30 minutes`.
+fn duration_words(duration: u32) -> String {
+    if duration == 0 {
+        return "0 seconds".to_string();
+    }
+    let mut words = Vec::new();
+    let mut left = duration;
+    for (secs, _, unit) in UNITS {
+        if left >= secs {
+            let n = left / secs;
+            words.push(format!("{n} {unit}{}", if n == 1 { "" } else { "s" }));
+            left %= secs;
+        }
+    }
+    words.join(" ")
+}
+
+/// Formats the data of a record whose header was just read across several lines, with
+/// comments naming the fields, like dig `+multiline`.
+///
+/// The continuation lines are indented by `indent` columns. Returns `false`, leaving
+/// the record data unread, if the record type is printed on a single line.
+pub fn fmt_record_data<W: Write>(
+    w: &mut W,
+    mr: &mut MessageReader,
+    marker: &RecordMarker,
+    indent: usize,
+) -> Result<bool> {
+    let pad = " ".repeat(indent);
+    let rtype = marker.rtype();
+    match rtype {
+        Type::SOA => {
+            let d = mr.record_data::<data::Soa>(marker)?;
+            writeln!(w, "{} {} (", d.mname, d.rname)?;
+            let width = [d.serial, d.refresh, d.retry, d.expire, d.minimum]
+                .iter()
+                .map(|n| n.to_string().len())
+                .max()
+                .unwrap_or(0);
+            writeln!(w, "{pad}{:<width$} ; serial", d.serial)?;
+            for (name, value) in [
+                ("refresh", d.refresh),
+                ("retry", d.retry),
+                ("expire", d.expire),
+                ("minimum", d.minimum),
+            ] {
+                writeln!(
+                    w,
+                    "{pad}{value:<width$} ; {name} ({})",
+                    duration_words(value)
+                )?;
+            }
+            write!(w, "{pad})")?;
+        }
+        Type::TXT => {
+            let strings = character_strings(mr.record_data_bytes(marker)?)?;
+            if strings.len() < 2 {
+                // a single string is printed as usual
+                let s = strings.first().copied().unwrap_or_default();
+                write!(w, "{}", character_string(s))?;
+                return Ok(true);
+            }
+            writeln!(w, "(")?;
+            for s in strings {
+                writeln!(w, "{pad}{}", character_string(s))?;
+            }
+            write!(w, "{pad})")?;
+        }
+        _ if rtype.value() == DNSKEY => {
+            let rdata = mr.record_data_bytes(marker)?;
+            if rdata.len() < 4 {
+                bail!("DNSKEY data too short: {} bytes", rdata.len());
+            }
+            let flags = u16::from_be_bytes([rdata[0], rdata[1]]);
+            let (protocol, algorithm) = (rdata[2], rdata[3]);
+            writeln!(w, "{flags} {protocol} {algorithm} (")?;
+            fmt_base64(w, &rdata[4..], &pad)?;
+            write!(w, "{pad}) ;")?;
+            match flags {
+                257 => write!(w, " KSK;")?,
+                256 => write!(w, " ZSK;")?,
+                _ => {}
+            }
+            write!(
+                w,
+                " alg = {} ; key id = {}",
+                algorithm_name(algorithm),
+                key_tag(rdata)
+            )?;
+        }
+        _ if rtype.value() == RRSIG => {
+            let rdata = mr.record_data_bytes(marker)?;
+            if rdata.len() < 18 {
+                bail!("RRSIG data too short: {} bytes", rdata.len());
+            }
+            let u16_at = |i: usize| u16::from_be_bytes([rdata[i], rdata[i + 1]]);
+            let u32_at =
+                |i: usize| u32::from_be_bytes([rdata[i], rdata[i + 1], rdata[i + 2], rdata[i + 3]]);
+            let (signer, signature) = uncompressed_name(&rdata[18..])?;
+            writeln!(
+                w,
+                "{} {} {} {} (",
+                Type::from(u16_at(0)),
+                rdata[2],
+                rdata[3],
+                u32_at(4)
+            )?;
+            writeln!(
+                w,
+                "{pad}{} {} {} {signer}",
+                timestamp(u32_at(8)),
+                timestamp(u32_at(12)),
+                u16_at(16)
+            )?;
+            fmt_base64(w, signature, &pad)?;
+            write!(w, "{pad})")?;
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn fmt_base64<W: Write>(w: &mut W, bytes: &[u8], pad: &str) -> Result<()> {
+    let encoded = Base64Engine.encode(bytes);
+    for line in encoded.as_bytes().chunks(BASE64_LINE_LEN) {
+        // base64 is ASCII
+        writeln!(w, "{pad}{}", std::str::from_utf8(line)?)?;
+    }
+    Ok(())
+}
+
+/// Reads the signer's name of an RRSIG record, which is never compressed.
+///
+/// Returns the name and the bytes following it.
+fn uncompressed_name(bytes: &[u8]) -> Result<(String, &[u8])> {
+    let mut name = String::new();
+    let mut pos = 0;
+    loop {
+        let Some(&len) = bytes.get(pos) else {
+            bail!("RRSIG signer's name overflows the record data");
+        };
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        if len & 0xC0 != 0 {
+            bail!("RRSIG signer's name is compressed");
+        }
+        let Some(text) = bytes.get(pos..pos + len as usize) else {
+            bail!("RRSIG signer's name overflows the record data");
+        };
+        name.push_str(&escape(text));
+        name.push('.');
+        pos += len as usize;
+    }
+    if name.is_empty() {
+        name.push('.');
+    }
+    Ok((name, &bytes[pos..]))
+}
+
+/// Formats a signature timestamp as `YYYYMMDDHHmmSS` in UTC.
+fn timestamp(secs: u32) -> String {
+    DateTime::from_timestamp(secs as i64, 0).map_or_else(
+        || secs.to_string(),
+        |t| t.format("%Y%m%d%H%M%S").to_string(),
+    )
+}
+
+/// Computes the key tag of a DNSKEY record, per RFC 4034 appendix B.
+fn key_tag(rdata: &[u8]) -> u16 {
+    let mut ac: u32 = 0;
+    for (i, b) in rdata.iter().enumerate() {
+        ac += if i & 1 == 0 {
+            (*b as u32) << 8
+        } else {
+            *b as u32
+        };
+    }
+    ac += (ac >> 16) & 0xFFFF;
+    (ac & 0xFFFF) as u16
+}
+
+fn algorithm_name(algorithm: u8) -> String {
+    let name = match algorithm {
+        5 => "RSASHA1",
+        7 => "NSEC3RSASHA1",
+        8 => "RSASHA256",
+        10 => "RSASHA512",
+        13 => "ECDSAP256SHA256",
+        14 => "ECDSAP384SHA384",
+        15 => "ED25519",
+        16 => "ED448",
+        _ => return algorithm.to_string(),
+    };
+    name.to_string()
+}
@@ -0,0 +1,625 @@
+use crate::fmt::{
+    Exchange,
+    save::{EncodedEdns, EncodedProtocol},
+};
+use anyhow::{Context, Result, bail};
+use rsdns::{
+    message::{RecordsSection, reader::MessageReader},
+    names::InlineName,
+    records::Type,
+};
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::Write,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The magic number of pcap files with nanosecond timestamps.
+const MAGIC_NANOS: u32 = 0xa1b23c4d;
+const MAGIC_MICROS: u32 = 0xa1b2c3d4;
+const PCAPNG_SHB: u32 = 0x0a0d0d0a;
+const PCAPNG_BYTE_ORDER: u32 = 0x1a2b3c4d;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LOOP: u32 = 108;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+
+const TCP_SYN: u8 = 0x02;
+const TCP_PSH_ACK: u8 = 0x18;
+
+/// Writes queries and responses to a pcap file as synthesized IP packets.
+///
+/// Packets carry the real nameserver address and the timestamps of the exchange.
+/// The local address is the `-b` source address, or the one the OS picks to reach
+/// the nameserver. An ephemeral port is made up from the message ID when the
+/// source port isn't known.
+pub struct Writer {
+    file: File,
+}
+
+impl Writer {
+    pub fn create(path: &str) -> Result<Writer> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .with_context(|| path.to_string())?;
+
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&MAGIC_NANOS.to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&4u16.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&(u16::MAX as u32).to_le_bytes());
+        header.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        file.write_all(&header)?;
+
+        Ok(Writer { file })
+    }
+
+    pub fn write(&mut self, ex: &Exchange) -> Result<()> {
+        let Some(server) = ex.ns else {
+            return Ok(());
+        };
+        if ex.msg.len() < 2 {
+            return Ok(());
+        }
+        let id = u16::from_be_bytes([ex.msg[0], ex.msg[1]]);
+        let tcp = ex.protocol.is_some_and(|p| p.transport == "tcp");
+        let client = local_addr(ex.protocol.and_then(|p| p.source.as_deref()), server, id);
+
+        let sent = ex.ts.unwrap_or(UNIX_EPOCH);
+        let received = sent + ex.elapsed.unwrap_or_default();
+
+        // sequence numbers as if the connection has just been established
+        let query_len = ex.query.map_or(0, |q| q.len() as u32 + 2);
+        if let Some(query) = ex.query {
+            self.write_packet(sent, &packet(client, server, tcp, 1, 1, query))?;
+        }
+        self.write_packet(
+            received,
+            &packet(server, client, tcp, 1, 1 + query_len, ex.msg),
+        )?;
+        self.file.flush()?;
+        Ok(())
+    }
+
+    fn write_packet(&mut self, ts: SystemTime, data: &[u8]) -> Result<()> {
+        let ts = ts.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut record = Vec::with_capacity(16 + data.len());
+        record.extend_from_slice(&(ts.as_secs() as u32).to_le_bytes());
+        record.extend_from_slice(&ts.subsec_nanos().to_le_bytes());
+        record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        record.extend_from_slice(data);
+        self.file.write_all(&record)?;
+        Ok(())
+    }
+}
+
+/// Finds the local address of the queries sent to `server`.
+fn local_addr(source: Option<&str>, server: SocketAddr, id: u16) -> SocketAddr {
+    let mut addr = source
+        .and_then(|s| s.parse::<SocketAddr>().ok())
+        .filter(|s| s.is_ipv4() == server.is_ipv4())
+        .unwrap_or_else(|| match server {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        });
+
+    if addr.ip().is_unspecified()
+        && let Ok(sock) = UdpSocket::bind(SocketAddr::new(addr.ip(), 0))
+        && sock.connect(server).is_ok()
+        && let Ok(local) = sock.local_addr()
+    {
+        addr.set_ip(local.ip());
+    }
+    if addr.port() == 0 {
+        addr.set_port(49152 + id % 16384);
+    }
+    addr
+}
+
+/// Builds an IP packet with a UDP datagram or a TCP segment carrying a DNS message.
+fn packet(src: SocketAddr, dst: SocketAddr, tcp: bool, seq: u32, ack: u32, msg: &[u8]) -> Vec<u8> {
+    let mut l4 = Vec::with_capacity(22 + msg.len());
+    l4.extend_from_slice(&src.port().to_be_bytes());
+    l4.extend_from_slice(&dst.port().to_be_bytes());
+    let checksum_at = if tcp {
+        l4.extend_from_slice(&seq.to_be_bytes());
+        l4.extend_from_slice(&ack.to_be_bytes());
+        l4.extend_from_slice(&[5 << 4, TCP_PSH_ACK]);
+        l4.extend_from_slice(&u16::MAX.to_be_bytes());
+        l4.extend_from_slice(&[0, 0, 0, 0]);
+        l4.extend_from_slice(&(msg.len() as u16).to_be_bytes());
+        16
+    } else {
+        l4.extend_from_slice(&((8 + msg.len()) as u16).to_be_bytes());
+        l4.extend_from_slice(&[0, 0]);
+        6
+    };
+    l4.extend_from_slice(msg);
+
+    let proto = if tcp { IPPROTO_TCP } else { IPPROTO_UDP };
+    let mut pseudo = Vec::with_capacity(40);
+    let mut ip = Vec::with_capacity(40);
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(s), IpAddr::V4(d)) => {
+            pseudo.extend_from_slice(&s.octets());
+            pseudo.extend_from_slice(&d.octets());
+            pseudo.extend_from_slice(&[0, proto]);
+            pseudo.extend_from_slice(&(l4.len() as u16).to_be_bytes());
+
+            ip.extend_from_slice(&[0x45, 0]);
+            ip.extend_from_slice(&((20 + l4.len()) as u16).to_be_bytes());
+            ip.extend_from_slice(&[0, 0, 0x40, 0, 64, proto, 0, 0]);
+            ip.extend_from_slice(&s.octets());
+            ip.extend_from_slice(&d.octets());
+            let sum = checksum(&[&ip]);
+            ip[10..12].copy_from_slice(&sum.to_be_bytes());
+        }
+        (s, d) => {
+            let s = to_ipv6(s);
+            let d = to_ipv6(d);
+            pseudo.extend_from_slice(&s.octets());
+            pseudo.extend_from_slice(&d.octets());
+            pseudo.extend_from_slice(&(l4.len() as u32).to_be_bytes());
+            pseudo.extend_from_slice(&[0, 0, 0, proto]);
+
+            ip.extend_from_slice(&[0x60, 0, 0, 0]);
+            ip.extend_from_slice(&(l4.len() as u16).to_be_bytes());
+            ip.extend_from_slice(&[proto, 64]);
+            ip.extend_from_slice(&s.octets());
+            ip.extend_from_slice(&d.octets());
+        }
+    }
+
+    let sum = match checksum(&[&pseudo, &l4]) {
+        // a zero UDP checksum means no checksum
+        0 if !tcp => 0xffff,
+        sum => sum,
+    };
+    l4[checksum_at..checksum_at + 2].copy_from_slice(&sum.to_be_bytes());
+
+    ip.extend_from_slice(&l4);
+    ip
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+/// Computes the Internet checksum (RFC 1071) of the concatenated chunks.
+///
+/// All chunks but the last must have an even length.
+fn checksum(chunks: &[&[u8]]) -> u16 {
+    let mut sum: u32 = 0;
+    for chunk in chunks {
+        for pair in chunk.chunks(2) {
+            let word = match *pair {
+                [hi, lo] => u16::from_be_bytes([hi, lo]),
+                [hi] => u16::from_be_bytes([hi, 0]),
+                _ => unreachable!(),
+            };
+            sum += word as u32;
+        }
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// A DNS message found in a capture.
+#[derive(Debug)]
+pub struct Message {
+    pub ts: SystemTime,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub tcp: bool,
+    pub msg: Vec<u8>,
+}
+
+impl Message {
+    pub fn id(&self) -> u16 {
+        u16::from_be_bytes([self.msg[0], self.msg[1]])
+    }
+
+    pub fn is_response(&self) -> bool {
+        self.msg[2] & 0x80 != 0
+    }
+}
+
+/// A link layer frame read from a capture.
+struct Frame<'a> {
+    ts: SystemTime,
+    linktype: u32,
+    data: &'a [u8],
+}
+
+/// A UDP datagram or a TCP segment.
+struct Segment<'a> {
+    src: SocketAddr,
+    dst: SocketAddr,
+    tcp: Option<(u32, u8)>,
+    payload: &'a [u8],
+}
+
+#[derive(Default)]
+struct Stream {
+    next_seq: Option<u32>,
+    buf: Vec<u8>,
+}
+
+/// Extracts the DNS messages sent to or from `port` in a pcap or pcapng file.
+///
+/// Messages over TCP are reassembled. Fragmented IP packets are skipped.
+pub fn load_all(path: &str, port: u16) -> Result<Vec<Message>> {
+    let data = std::fs::read(path).with_context(|| path.to_string())?;
+    let frames = frames(&data).with_context(|| path.to_string())?;
+
+    let mut messages = Vec::new();
+    let mut streams: HashMap<(SocketAddr, SocketAddr), Stream> = HashMap::new();
+
+    for frame in frames {
+        let Some(seg) = segment(&frame) else {
+            continue;
+        };
+        if seg.src.port() != port && seg.dst.port() != port {
+            continue;
+        }
+
+        let Some((seq, flags)) = seg.tcp else {
+            if seg.payload.len() >= 12 {
+                messages.push(Message {
+                    ts: frame.ts,
+                    src: seg.src,
+                    dst: seg.dst,
+                    tcp: false,
+                    msg: seg.payload.to_vec(),
+                });
+            }
+            continue;
+        };
+
+        let stream = streams.entry((seg.src, seg.dst)).or_default();
+        if flags & TCP_SYN != 0 {
+            stream.next_seq = Some(seq.wrapping_add(1));
+            stream.buf.clear();
+            continue;
+        }
+        if seg.payload.is_empty() {
+            continue;
+        }
+        let mut payload = seg.payload;
+        if let Some(next) = stream.next_seq {
+            let behind = next.wrapping_sub(seq) as i32;
+            if behind > 0 {
+                // a retransmission, keep the new data only
+                if behind as usize >= payload.len() {
+                    continue;
+                }
+                payload = &payload[behind as usize..];
+            } else if behind < 0 {
+                // a gap in the capture, the buffered data can't be completed
+                stream.buf.clear();
+            }
+        }
+        stream.next_seq = Some(seq.wrapping_add(seg.payload.len() as u32));
+        stream.buf.extend_from_slice(payload);
+
+        while stream.buf.len() >= 2 {
+            let len = u16::from_be_bytes([stream.buf[0], stream.buf[1]]) as usize;
+            if stream.buf.len() < 2 + len {
+                break;
+            }
+            let msg: Vec<u8> = stream.buf.drain(..2 + len).skip(2).collect();
+            if msg.len() >= 12 {
+                messages.push(Message {
+                    ts: frame.ts,
+                    src: seg.src,
+                    dst: seg.dst,
+                    tcp: true,
+                    msg,
+                });
+            }
+        }
+    }
+
+    Ok(messages)
+}
+
+fn frames(data: &[u8]) -> Result<Vec<Frame<'_>>> {
+    if data.len() < 4 {
+        bail!("not a pcap file");
+    }
+    let magic = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    if magic == PCAPNG_SHB {
+        return pcapng_frames(data);
+    }
+
+    let (le, nanos) = match (magic, magic.swap_bytes()) {
+        (MAGIC_MICROS, _) => (true, false),
+        (MAGIC_NANOS, _) => (true, true),
+        (_, MAGIC_MICROS) => (false, false),
+        (_, MAGIC_NANOS) => (false, true),
+        _ => bail!("not a pcap file"),
+    };
+    let r = Reader { data, le };
+    if data.len() < 24 {
+        bail!("truncated pcap header");
+    }
+    let linktype = r.u32(20) & 0x0fff_ffff;
+
+    let mut frames = Vec::new();
+    let mut pos = 24;
+    while pos + 16 <= data.len() {
+        let secs = r.u32(pos) as u64;
+        let frac = r.u32(pos + 4);
+        let len = r.u32(pos + 8) as usize;
+        let start = pos + 16;
+        // tolerate a partial last record
+        let Some(frame) = data.get(start..start + len) else {
+            break;
+        };
+        let frac = if nanos {
+            Duration::from_nanos(frac as u64)
+        } else {
+            Duration::from_micros(frac as u64)
+        };
+        frames.push(Frame {
+            ts: UNIX_EPOCH + Duration::from_secs(secs) + frac,
+            linktype,
+            data: frame,
+        });
+        pos = start + len;
+    }
+    Ok(frames)
+}
+
+fn pcapng_frames(data: &[u8]) -> Result<Vec<Frame<'_>>> {
+    // link type and timestamp units per second of every interface of the current section
+    let mut interfaces: Vec<(u32, u64)> = Vec::new();
+    let mut r = Reader { data, le: true };
+    let mut frames = Vec::new();
+    let mut pos = 0;
+
+    while pos + 12 <= data.len() {
+        let block_type =
+            u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]);
+        if block_type == PCAPNG_SHB {
+            let bom = &data[pos + 8..pos + 12];
+            r.le = u32::from_le_bytes([bom[0], bom[1], bom[2], bom[3]]) == PCAPNG_BYTE_ORDER;
+            interfaces.clear();
+        }
+        let block_type = r.u32(pos);
+        let len = r.u32(pos + 4) as usize;
+        if len < 12 || pos + len > data.len() {
+            break;
+        }
+        let body = pos + 8;
+        let body_end = pos + len - 4;
+
+        match block_type {
+            // interface description
+            1 if body + 8 <= body_end => {
+                let linktype = r.u16(body) as u32;
+                let mut resolution = 1_000_000;
+                let mut opt = body + 8;
+                while opt + 4 <= body_end {
+                    let code = r.u16(opt);
+                    let opt_len = r.u16(opt + 2) as usize;
+                    if code == 0 {
+                        break;
+                    }
+                    if code == 9 && opt_len == 1 {
+                        let v = data[opt + 4];
+                        resolution = if v & 0x80 == 0 {
+                            10u64.saturating_pow(v as u32)
+                        } else {
+                            2u64.saturating_pow((v & 0x7f) as u32)
+                        };
+                    }
+                    opt += 4 + opt_len.div_ceil(4) * 4;
+                }
+                interfaces.push((linktype, resolution));
+            }
+            // enhanced packet
+            6 if body + 20 <= body_end => {
+                let interface = r.u32(body) as usize;
+                let ts = ((r.u32(body + 4) as u64) << 32) | r.u32(body + 8) as u64;
+                let caplen = r.u32(body + 12) as usize;
+                if let (Some(&(linktype, resolution)), Some(frame)) = (
+                    interfaces.get(interface),
+                    data.get(body + 20..body + 20 + caplen),
+                ) {
+                    frames.push(Frame {
+                        ts: pcapng_time(ts, resolution),
+                        linktype,
+                        data: frame,
+                    });
+                }
+            }
+            // simple packet, no timestamp
+            3 if body + 4 <= body_end => {
+                let caplen = (r.u32(body) as usize).min(body_end - body - 4);
+                if let Some(&(linktype, _)) = interfaces.first() {
+                    frames.push(Frame {
+                        ts: UNIX_EPOCH,
+                        linktype,
+                        data: &data[body + 4..body + 4 + caplen],
+                    });
+                }
+            }
+            _ => {}
+        }
+        pos += len;
+    }
+    Ok(frames)
+}
+
+fn pcapng_time(ts: u64, resolution: u64) -> SystemTime {
+    let secs = ts / resolution;
+    let nanos = (ts % resolution) as u128 * 1_000_000_000 / resolution as u128;
+    UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_nanos(nanos as u64)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    le: bool,
+}
+
+impl Reader<'_> {
+    fn u16(&self, pos: usize) -> u16 {
+        let b = [self.data[pos], self.data[pos + 1]];
+        if self.le {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        }
+    }
+
+    fn u32(&self, pos: usize) -> u32 {
+        let b = [
+            self.data[pos],
+            self.data[pos + 1],
+            self.data[pos + 2],
+            self.data[pos + 3],
+        ];
+        if self.le {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        }
+    }
+}
+
+/// Strips the link layer header and decodes the IP and transport headers.
+fn segment<'a>(frame: &Frame<'a>) -> Option<Segment<'a>> {
+    let d = frame.data;
+    let ip = match frame.linktype {
+        LINKTYPE_ETHERNET => {
+            let mut pos = 12;
+            let mut ethertype = u16::from_be_bytes([*d.get(pos)?, *d.get(pos + 1)?]);
+            // VLAN tags
+            while ethertype == 0x8100 || ethertype == 0x88a8 {
+                pos += 4;
+                ethertype = u16::from_be_bytes([*d.get(pos)?, *d.get(pos + 1)?]);
+            }
+            d.get(pos + 2..)?
+        }
+        LINKTYPE_NULL | LINKTYPE_LOOP => d.get(4..)?,
+        LINKTYPE_LINUX_SLL => d.get(16..)?,
+        LINKTYPE_LINUX_SLL2 => d.get(20..)?,
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => d,
+        _ => return None,
+    };
+
+    let (src, dst, proto, l4) = match ip.first()? >> 4 {
+        4 => {
+            let ihl = ((ip[0] & 0x0f) as usize) * 4;
+            let total = (u16::from_be_bytes([*ip.get(2)?, *ip.get(3)?]) as usize).min(ip.len());
+            let frag = u16::from_be_bytes([*ip.get(6)?, *ip.get(7)?]);
+            if frag & 0x3fff != 0 {
+                return None;
+            }
+            let src: [u8; 4] = ip.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = ip.get(16..20)?.try_into().ok()?;
+            (
+                IpAddr::from(src),
+                IpAddr::from(dst),
+                ip[9],
+                ip.get(ihl..total)?,
+            )
+        }
+        6 => {
+            let payload_len = u16::from_be_bytes([*ip.get(4)?, *ip.get(5)?]) as usize;
+            let src: [u8; 16] = ip.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = ip.get(24..40)?.try_into().ok()?;
+            let mut next = ip[6];
+            let mut l4 = ip.get(40..(40 + payload_len).min(ip.len()))?;
+            // hop-by-hop, routing and destination options headers
+            while matches!(next, 0 | 43 | 60) {
+                let len = (*l4.get(1)? as usize + 1) * 8;
+                next = l4[0];
+                l4 = l4.get(len..)?;
+            }
+            (IpAddr::from(src), IpAddr::from(dst), next, l4)
+        }
+        _ => return None,
+    };
+
+    let sport = u16::from_be_bytes([*l4.first()?, *l4.get(1)?]);
+    let dport = u16::from_be_bytes([*l4.get(2)?, *l4.get(3)?]);
+    let (tcp, payload) = match proto {
+        IPPROTO_UDP => {
+            let len = (u16::from_be_bytes([*l4.get(4)?, *l4.get(5)?]) as usize).min(l4.len());
+            (None, l4.get(8..len)?)
+        }
+        IPPROTO_TCP => {
+            let seq = u32::from_be_bytes(l4.get(4..8)?.try_into().ok()?);
+            let offset = ((*l4.get(12)? >> 4) as usize) * 4;
+            (Some((seq, *l4.get(13)?)), l4.get(offset..)?)
+        }
+        _ => return None,
+    };
+
+    Some(Segment {
+        src: SocketAddr::new(src, sport),
+        dst: SocketAddr::new(dst, dport),
+        tcp,
+        payload,
+    })
+}
+
+/// Reconstructs the protocol info of a response read from a capture.
+pub fn protocol(query: Option<&Message>, resp: &Message) -> EncodedProtocol {
+    let mut qclass = None;
+    if let Ok(mut mr) = MessageReader::new(&resp.msg)
+        && mr.header().is_ok()
+        && let Ok(q) = mr.the_question()
+    {
+        qclass = Some(q.qclass.to_string());
+    }
+    EncodedProtocol {
+        transport: if resp.tcp { "tcp" } else { "udp" }.to_string(),
+        transport_inferred: false,
+        edns: query.and_then(|q| query_edns(&q.msg)),
+        recursion: resp.msg[2] & 0x01 != 0,
+        qclass: qclass.unwrap_or_default(),
+        source: Some(resp.dst.to_string()),
+    }
+}
+
+fn query_edns(msg: &[u8]) -> Option<EncodedEdns> {
+    let mut mr = MessageReader::new(msg).ok()?;
+    mr.header().ok()?;
+    mr.the_question().ok()?;
+    while mr.has_records() {
+        let header = mr.record_header::<InlineName>().ok()?;
+        if header.section() == RecordsSection::Additional && header.rtype() == Type::OPT {
+            let opt = mr.opt_record(header.marker()).ok()?;
+            return Some(EncodedEdns {
+                version: opt.version(),
+                udp_payload_size: opt.udp_payload_size(),
+            });
+        }
+        mr.skip_record_data(header.marker()).ok()?;
+    }
+    None
+}
@@ -0,0 +1,169 @@
+use crate::{fmt::summary::Summary, resolvers::Resolver};
+use anyhow::Result;
+use rsdns::message::RCode;
+use std::{fmt::Write, net::SocketAddr};
+
+const ADDR_WIDTH: usize = 24;
+const NAME_WIDTH: usize = 12;
+const STATUS_WIDTH: usize = 10;
+const TTL_WIDTH: usize = 8;
+const MATCH_WIDTH: usize = 10;
+
+#[derive(Debug, Default)]
+struct Tally {
+    key: String,
+    expect: bool,
+    total: usize,
+    matched: usize,
+}
+
+/// Tracks the resolvers reported so far for the current question.
+#[derive(Debug, Default)]
+pub struct Report {
+    tally: Option<Tally>,
+}
+
+impl Report {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a new question block if `key` differs from the current one.
+    pub fn begin<W: Write>(&mut self, w: &mut W, key: &str, expect: &[String]) -> Result<()> {
+        if self.tally.as_ref().is_some_and(|t| t.key == key) {
+            return Ok(());
+        }
+
+        if self.tally.is_some() {
+            self.end(w)?;
+            writeln!(w)?;
+        }
+
+        write!(w, ";; PROPAGATION: {key}")?;
+        if !expect.is_empty() {
+            write!(w, ", expect: {}", expect.join(", "))?;
+        }
+        writeln!(w)?;
+        writeln!(
+            w,
+            ";{:addr_w$}{:name_w$}{:status_w$}{:ttl_w$}{:match_w$}ANSWER",
+            "SERVER",
+            "NAME",
+            "STATUS",
+            "TTL",
+            if expect.is_empty() { "" } else { "MATCH" },
+            addr_w = ADDR_WIDTH - 1,
+            name_w = NAME_WIDTH,
+            status_w = STATUS_WIDTH,
+            ttl_w = TTL_WIDTH,
+            match_w = if expect.is_empty() { 0 } else { MATCH_WIDTH },
+        )?;
+
+        self.tally = Some(Tally {
+            key: key.to_string(),
+            expect: !expect.is_empty(),
+            ..Default::default()
+        });
+        Ok(())
+    }
+
+    /// Prints the summary line of the current question block, if any.
+    pub fn end<W: Write>(&mut self, w: &mut W) -> Result<()> {
+        if let Some(tally) = self.tally.take() {
+            if tally.expect {
+                writeln!(w, ";; {} of {} resolvers match", tally.matched, tally.total)?;
+            } else {
+                writeln!(w, ";; {} resolvers queried", tally.total)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn fmt_line<W: Write>(
+        &mut self,
+        w: &mut W,
+        ns: Option<SocketAddr>,
+        resolver: Option<&Resolver>,
+        summary: &Summary,
+        expect: &[String],
+    ) -> Result<()> {
+        fmt_server(w, ns, resolver)?;
+        write!(w, "{:status_w$}", summary.rcode, status_w = STATUS_WIDTH)?;
+        match summary.min_ttl {
+            Some(ttl) => write!(w, "{:<ttl_w$}", ttl, ttl_w = TTL_WIDTH)?,
+            None => write!(w, "{:ttl_w$}", "-", ttl_w = TTL_WIDTH)?,
+        }
+
+        if let Some(ref mut tally) = self.tally {
+            tally.total += 1;
+        }
+
+        if !expect.is_empty() {
+            let matched = summary.rcode == RCode::NOERROR && matches(summary, expect);
+            if matched && let Some(ref mut tally) = self.tally {
+                tally.matched += 1;
+            }
+            write!(
+                w,
+                "{:match_w$}",
+                if matched { "yes" } else { "no" },
+                match_w = MATCH_WIDTH
+            )?;
+        }
+
+        if summary.answers.is_empty() {
+            write!(w, "-")?;
+        } else {
+            write!(w, "{}", summary.answers.join(", "))?;
+        }
+        Ok(())
+    }
+
+    pub fn fmt_error_line<W: Write>(
+        &mut self,
+        w: &mut W,
+        ns: Option<SocketAddr>,
+        resolver: Option<&Resolver>,
+        err: &anyhow::Error,
+    ) -> Result<()> {
+        if let Some(ref mut tally) = self.tally {
+            tally.total += 1;
+        }
+        fmt_server(w, ns, resolver)?;
+        write!(w, "error: {err:#}")?;
+        Ok(())
+    }
+}
+
+fn fmt_server<W: Write>(
+    w: &mut W,
+    ns: Option<SocketAddr>,
+    resolver: Option<&Resolver>,
+) -> Result<()> {
+    let addr = ns.map(|ns| ns.to_string()).unwrap_or_default();
+    let name = resolver.map(|r| r.name.as_str()).unwrap_or_default();
+    write!(
+        w,
+        "{:addr_w$}{:name_w$}",
+        addr,
+        name,
+        addr_w = ADDR_WIDTH,
+        name_w = NAME_WIDTH
+    )?;
+    Ok(())
+}
+
+/// Normalizes record data for comparison: case, trailing dots and quotes are ignored.
+fn normalize(rdata: &str) -> String {
+    rdata
+        .split_whitespace()
+        .map(|s| s.trim_matches('"').trim_end_matches('.').to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Checks that every expected record data is found in the answer.
+fn matches(summary: &Summary, expect: &[String]) -> bool {
+    let answers: Vec<String> = summary.answers.iter().map(|a| normalize(a)).collect();
+    expect.iter().all(|e| answers.contains(&normalize(e)))
+}
@@ -1,5 +1,8 @@
-use anyhow::Result;
-use rsdns::records::data;
+use anyhow::{Result, bail};
+use rsdns::{
+    message::reader::{MessageReader, RecordMarker},
+    records::{Type, data},
+};
 use std::fmt::Write;
 
 macro_rules! obsolete {
@@ -14,11 +17,54 @@ macro_rules! obsolete {
     };
 }
 
-fn str_from_text(text: &[u8]) -> &str {
-    match std::str::from_utf8(text) {
-        Ok(s) => s,
-        Err(_) => "__CH4_NON_UTF8_STRING__",
+/// Formats a character-string in the presentation format of RFC 1035 section 5.1:
+/// quoted, with quotes and backslashes escaped, and the non-printable bytes as `\DDD`.
+pub fn character_string(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() + 2);
+    s.push('"');
+    for &b in bytes {
+        match b {
+            b'"' | b'\\' => {
+                s.push('\\');
+                s.push(b as char);
+            }
+            0x20..=0x7E => s.push(b as char),
+            _ => {
+                let _ = write!(s, "\\{b:03}");
+            }
+        }
+    }
+    s.push('"');
+    s
+}
+
+/// Splits TXT record data into its character-strings.
+pub fn character_strings(mut rdata: &[u8]) -> Result<Vec<&[u8]>> {
+    let mut strings = Vec::new();
+    while let Some((&len, rest)) = rdata.split_first() {
+        let len = len as usize;
+        if rest.len() < len {
+            bail!("character-string overflows the record data");
+        }
+        strings.push(&rest[..len]);
+        rdata = &rest[len..];
     }
+    Ok(strings)
+}
+
+/// Formats TXT record data as its character-strings, separated by spaces.
+pub fn fmt_txt<W: Write>(w: &mut W, rdata: &[u8]) -> Result<()> {
+    let strings = character_strings(rdata)?;
+    if strings.is_empty() {
+        write!(w, "\"\"")?;
+    }
+    for (i, s) in strings.into_iter().enumerate() {
+        if i > 0 {
+            write!(w, " ")?;
+        }
+        write!(w, "{}", character_string(s))?;
+    }
+    Ok(())
 }
 
 pub trait RDataFormatter<W: Write, D: data::RData> {
@@ -27,6 +73,58 @@ pub trait RDataFormatter<W: Write, D: data::RData> {
 
 pub struct RDataFmt;
 
+macro_rules! fmt_rdata {
+    ($w:ident, $mr:ident, $marker:ident, $RR:ident) => {{
+        let d = $mr.record_data::<data::$RR>($marker)?;
+        RDataFmt::fmt($w, &d)?;
+    }};
+}
+
+/// Formats the data of a record whose header was just read.
+///
+/// Returns `false`, leaving the record data unread, if the record type has no formatter.
+pub fn fmt_record_data<W: Write>(
+    w: &mut W,
+    mr: &mut MessageReader,
+    marker: &RecordMarker,
+) -> Result<bool> {
+    match marker.rtype() {
+        Type::A => fmt_rdata!(w, mr, marker, A),
+        Type::AAAA => fmt_rdata!(w, mr, marker, Aaaa),
+        Type::CNAME => fmt_rdata!(w, mr, marker, Cname),
+        Type::NS => fmt_rdata!(w, mr, marker, Ns),
+        Type::SOA => fmt_rdata!(w, mr, marker, Soa),
+        Type::PTR => fmt_rdata!(w, mr, marker, Ptr),
+        Type::MX => fmt_rdata!(w, mr, marker, Mx),
+        // rsdns joins the strings, they are split from the raw data
+        Type::TXT => fmt_txt(w, mr.record_data_bytes(marker)?)?,
+        Type::HINFO => fmt_rdata!(w, mr, marker, Hinfo),
+        Type::SRV => fmt_rdata!(w, mr, marker, Srv),
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// Formats the data of a record whose header was just read, in the generic format of
+/// RFC 3597 section 5 if the record type has no formatter.
+pub fn fmt_record_data_or_generic<W: Write>(
+    w: &mut W,
+    mr: &mut MessageReader,
+    marker: &RecordMarker,
+) -> Result<()> {
+    if !fmt_record_data(w, mr, marker)? {
+        let bytes = mr.record_data_bytes(marker)?;
+        write!(w, "\\# {}", bytes.len())?;
+        if !bytes.is_empty() {
+            write!(w, " ")?;
+        }
+        for b in bytes {
+            write!(w, "{b:02x}")?;
+        }
+    }
+    Ok(())
+}
+
 impl<W: Write> RDataFormatter<W, data::A> for RDataFmt {
     fn fmt(w: &mut W, d: &data::A) -> Result<()> {
         write!(w, "{}", d.address)?;
@@ -79,9 +177,9 @@ impl<W: Write> RDataFormatter<W, data::Hinfo> for RDataFmt {
     fn fmt(w: &mut W, d: &data::Hinfo) -> Result<()> {
         write!(
             w,
-            "\"{}\" \"{}\"",
-            str_from_text(&d.cpu),
-            str_from_text(&d.os)
+            "{} {}",
+            character_string(&d.cpu),
+            character_string(&d.os)
         )?;
         Ok(())
     }
@@ -96,14 +194,6 @@ impl<W: Write> RDataFormatter<W, data::Mx> for RDataFmt {
     }
 }
 
-impl<W: Write> RDataFormatter<W, data::Txt> for RDataFmt {
-    fn fmt(w: &mut W, d: &data::Txt) -> Result<()> {
-        let utf8 = str_from_text(&d.text);
-        write!(w, "\"{utf8}\"")?;
-        Ok(())
-    }
-}
-
 impl<W: Write> RDataFormatter<W, data::Aaaa> for RDataFmt {
     fn fmt(w: &mut W, d: &data::Aaaa) -> Result<()> {
         write!(w, "{}", d.address)?;
@@ -1,6 +1,13 @@
-use crate::fmt::rdata::{RDataFmt, RDataFormatter};
+use crate::fmt::rdata::{RDataFmt, RDataFormatter, fmt_txt};
 use anyhow::Result;
-use rsdns::records::{RecordSet, data::RData};
+use rsdns::{
+    message::{RecordsSection, reader::MessageReader},
+    names::InlineName,
+    records::{
+        RecordSet, Type,
+        data::{self, RData},
+    },
+};
 use std::fmt::Write;
 
 pub fn fmt_short<W, D>(w: &mut W, rrset: &RecordSet<D>) -> Result<()>
@@ -15,3 +22,32 @@ where
     }
     Ok(())
 }
+
+/// Formats the records of a TXT RRset read from `msg`.
+///
+/// rsdns joins the character-strings of a record, they are split from the record data.
+pub fn fmt_short_txt<W: Write>(w: &mut W, msg: &[u8], rrset: &RecordSet<data::Txt>) -> Result<()> {
+    let mut mr = MessageReader::new(msg)?;
+    mr.header()?;
+    while mr.has_questions() {
+        mr.question()?;
+    }
+    while mr.has_records() {
+        let header = mr.record_header::<InlineName>()?;
+        let marker = header.marker();
+        if header.section() == RecordsSection::Answer
+            && header.rtype() == Type::TXT
+            && header.rclass() == rrset.rclass
+            && header
+                .name()
+                .as_str()
+                .eq_ignore_ascii_case(rrset.name.as_str())
+        {
+            fmt_txt(w, mr.record_data_bytes(marker)?)?;
+            writeln!(w)?;
+        } else {
+            mr.skip_record_data(marker)?;
+        }
+    }
+    Ok(())
+}
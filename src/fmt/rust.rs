@@ -1,27 +1,80 @@
 use anyhow::Result;
-use rsdns::records::Type;
-use std::fmt::Write;
+use base64::{Engine as _, engine::general_purpose::STANDARD as Base64Engine};
+use rsdns::{
+    message::{RCode, RecordsSection, reader::MessageReader},
+    names::{InlineName, Name},
+    records::{Class, Type, data},
+};
+use std::{
+    collections::BTreeSet,
+    fmt::{Display, Write},
+    net::{Ipv4Addr, Ipv6Addr},
+};
 
 const CHUNK_LEN: usize = 12;
+const XXD_CHUNK_LEN: usize = 16;
 
 #[inline]
 fn is_ascii_printable(b: &u8) -> bool {
     (0x20..=0x7E).contains(b)
 }
 
+/// A language to format a message as a byte array in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    Rust,
+    C,
+    Python,
+    Go,
+}
+
+impl Lang {
+    fn comment(self) -> &'static str {
+        match self {
+            Lang::Python => "#",
+            _ => "//",
+        }
+    }
+
+    fn open<W: Write>(self, w: &mut W, name: &str, len: usize) -> Result<()> {
+        match self {
+            Lang::Rust => {
+                writeln!(w, "#[rustfmt::skip]")?;
+                writeln!(w, "const {name}: [u8; {len}] = [")?;
+            }
+            Lang::C => writeln!(w, "static const unsigned char {name}[{len}] = {{")?,
+            Lang::Python => writeln!(w, "{name} = bytes([")?,
+            Lang::Go => writeln!(w, "var {name} = [{len}]byte{{")?,
+        }
+        Ok(())
+    }
+
+    fn close(self) -> &'static str {
+        match self {
+            Lang::Rust => "];",
+            Lang::C => "};",
+            Lang::Python => "])",
+            Lang::Go => "}",
+        }
+    }
+}
+
+/// Formats a message as a byte array, annotated with the printable characters and the
+/// offset of every line.
 pub fn fmt<W: Write>(
     w: &mut W,
+    lang: Lang,
     qtype: Option<Type>,
     qname: Option<&str>,
     name: &str,
     msg: &[u8],
 ) -> Result<()> {
+    let comment = lang.comment();
     if let (Some(qt), Some(qn)) = (qtype, qname) {
-        writeln!(w, "// {qt} {qn}")?;
+        writeln!(w, "{comment} {qt} {qn}")?;
     }
 
-    writeln!(w, "#[rustfmt::skip]")?;
-    writeln!(w, "const {}: [u8; {}] = [", name, msg.len())?;
+    lang.open(w, name, msg.len())?;
 
     let chunks = msg.chunks(CHUNK_LEN);
     let mut max_chunk_len = 0;
@@ -42,30 +95,315 @@ pub fn fmt<W: Write>(
         // fill the last, possibly shorter, line
         //
         if len < max_chunk_len {
-            write!(w, " /*")?;
-            let n_spaces = (max_chunk_len - len) * 6 - 4 - 1;
-            for _ in 0..n_spaces {
-                write!(w, " ")?;
+            let width = (max_chunk_len - len) * 6;
+            if lang == Lang::Python {
+                write!(w, "{:width$}", "")?;
+            } else {
+                write!(w, " /*{:1$}*/", "", width - 4 - 1)?;
             }
-            write!(w, "*/")?;
         }
 
         //
         // visual comment
         //
-        write!(w, " // |")?;
-        for b in chunk {
-            let c = if is_ascii_printable(b) {
-                std::str::from_utf8(std::slice::from_ref(b)).unwrap()
+        writeln!(w, " {comment} |{}| {}", ascii(chunk), (index * CHUNK_LEN))?;
+    }
+
+    writeln!(w, "{}", lang.close())?;
+    Ok(())
+}
+
+/// Replaces the non-printable bytes with dots.
+fn ascii(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| {
+            if is_ascii_printable(b) {
+                *b as char
             } else {
-                "."
-            };
-            write!(w, "{c}")?;
+                '.'
+            }
+        })
+        .collect()
+}
+
+/// Formats a message as continuous hex digits, on a single line.
+pub fn fmt_hex<W: Write>(w: &mut W, msg: &[u8]) -> Result<()> {
+    for b in msg {
+        write!(w, "{b:02x}")?;
+    }
+    writeln!(w)?;
+    Ok(())
+}
+
+/// Formats a message like `xxd`, which converts it back to binary with `xxd -r`.
+///
+/// The message is followed by an empty line, separating it from the next one.
+pub fn fmt_xxd<W: Write>(w: &mut W, msg: &[u8]) -> Result<()> {
+    for (index, chunk) in msg.chunks(XXD_CHUNK_LEN).enumerate() {
+        let mut hex = String::new();
+        for (i, pair) in chunk.chunks(2).enumerate() {
+            if i > 0 {
+                hex.push(' ');
+            }
+            for b in pair {
+                write!(hex, "{b:02x}")?;
+            }
+        }
+        writeln!(
+            w,
+            "{:08x}: {hex:39}  {}",
+            index * XXD_CHUNK_LEN,
+            ascii(chunk)
+        )?;
+    }
+    writeln!(w)?;
+    Ok(())
+}
+
+/// Formats a message as standard base64, on a single line.
+pub fn fmt_base64<W: Write>(w: &mut W, msg: &[u8]) -> Result<()> {
+    writeln!(w, "{}", Base64Engine.encode(msg))?;
+    Ok(())
+}
+
+/// Collects the `use` declarations needed by a generated test.
+#[derive(Default)]
+struct Uses(BTreeSet<&'static str>);
+
+impl Uses {
+    fn add(&mut self, path: &'static str) -> &'static str {
+        self.0.insert(path);
+        path.rsplit("::").next().unwrap()
+    }
+}
+
+/// Formats a `#[test]` function parsing the array `name` with rsdns `MessageReader`.
+///
+/// The test asserts the header counts, the response code and the questions, and every
+/// answer record along with its parsed data. The other sections are only skipped.
+pub fn fmt_test<W: Write>(w: &mut W, name: &str, msg: &[u8]) -> Result<()> {
+    let mut uses = Uses::default();
+    let mut body = String::new();
+    let b = &mut body;
+
+    let mr = uses.add("rsdns::message::reader::MessageReader");
+    writeln!(b, "    let mut mr = {mr}::new(&{name}).unwrap();")?;
+
+    let mut reader = MessageReader::new(msg)?;
+    let header = reader.header()?;
+    writeln!(b, "    let header = mr.header().unwrap();")?;
+    writeln!(b, "    assert_eq!(header.id, {:#06x});", header.id)?;
+    writeln!(
+        b,
+        "    assert_eq!(header.flags.response_code(), {});",
+        rcode(&mut uses, header.flags.response_code())
+    )?;
+    writeln!(b, "    assert_eq!(header.qd_count, {});", header.qd_count)?;
+    writeln!(b, "    assert_eq!(header.an_count, {});", header.an_count)?;
+    writeln!(b, "    assert_eq!(header.ns_count, {});", header.ns_count)?;
+    writeln!(b, "    assert_eq!(header.ar_count, {});", header.ar_count)?;
+
+    while reader.has_questions() {
+        let q = reader.question()?;
+        writeln!(b)?;
+        writeln!(b, "    let q = mr.question().unwrap();")?;
+        writeln!(b, "    assert_eq!(q.qname, {:?});", q.qname.as_str())?;
+        writeln!(b, "    assert_eq!(q.qtype, {});", rtype(&mut uses, q.qtype))?;
+        writeln!(
+            b,
+            "    assert_eq!(q.qclass, {});",
+            rclass(&mut uses, q.qclass)
+        )?;
+    }
+
+    while reader.has_records_in(RecordsSection::Answer) {
+        let rh = reader.record_header::<InlineName>()?;
+        let marker = rh.marker();
+        writeln!(b)?;
+        writeln!(
+            b,
+            "    let rh = mr.record_header::<{}>().unwrap();",
+            uses.add("rsdns::names::InlineName")
+        )?;
+        writeln!(
+            b,
+            "    assert_eq!(rh.section(), {}::Answer);",
+            uses.add("rsdns::message::RecordsSection")
+        )?;
+        writeln!(b, "    assert_eq!(*rh.name(), {:?});", rh.name().as_str())?;
+        writeln!(
+            b,
+            "    assert_eq!(rh.rtype(), {});",
+            rtype(&mut uses, rh.rtype())
+        )?;
+        writeln!(
+            b,
+            "    assert_eq!(rh.rclass(), {});",
+            rclass(&mut uses, rh.rclass())
+        )?;
+        writeln!(b, "    assert_eq!(rh.ttl(), {});", rh.ttl())?;
+
+        macro_rules! rdata {
+            ($RR:ident, $($field:ident => $value:expr),+) => {{
+                let d = reader.record_data::<data::$RR>(marker)?;
+                writeln!(
+                    b,
+                    "    let rdata = mr.record_data::<{}::{}>(rh.marker()).unwrap();",
+                    uses.add("rsdns::records::data"),
+                    stringify!($RR)
+                )?;
+                $(
+                    let value = $value(&d.$field, &mut uses);
+                    writeln!(b, "    assert_eq!(rdata.{}, {value});", stringify!($field))?;
+                )+
+            }};
         }
 
-        writeln!(w, "| {}", (index * CHUNK_LEN))?;
+        match rh.rtype() {
+            Type::A => rdata!(A, address => ipv4),
+            Type::AAAA => rdata!(Aaaa, address => ipv6),
+            Type::CNAME => rdata!(Cname, cname => dn),
+            Type::NS => rdata!(Ns, nsdname => dn),
+            Type::PTR => rdata!(Ptr, ptrdname => dn),
+            Type::MX => rdata!(Mx, preference => num, exchange => dn),
+            Type::SOA => rdata!(
+                Soa,
+                mname => dn,
+                rname => dn,
+                serial => num,
+                refresh => num,
+                retry => num,
+                expire => num,
+                minimum => num
+            ),
+            Type::TXT => rdata!(Txt, text => text),
+            Type::HINFO => rdata!(Hinfo, cpu => text, os => text),
+            Type::SRV => rdata!(
+                Srv,
+                priority => num,
+                weight => num,
+                port => num,
+                target => dn
+            ),
+            _ => {
+                let bytes = reader.record_data_bytes(marker)?;
+                writeln!(
+                    b,
+                    "    assert_eq!(mr.record_data_bytes(rh.marker()).unwrap(), {});",
+                    byte_str(bytes)
+                )?;
+            }
+        }
+    }
+
+    if reader.has_records() {
+        writeln!(b)?;
+        writeln!(b, "    // the authority and additional sections")?;
+        writeln!(b, "    while mr.has_records() {{")?;
+        writeln!(
+            b,
+            "        let rh = mr.record_header::<{}>().unwrap();",
+            uses.add("rsdns::names::InlineName")
+        )?;
+        writeln!(b, "        mr.skip_record_data(rh.marker()).unwrap();")?;
+        writeln!(b, "    }}")?;
     }
 
-    writeln!(w, "];")?;
+    writeln!(w, "#[test]")?;
+    writeln!(w, "fn {}() {{", name.to_lowercase())?;
+    for path in &uses.0 {
+        writeln!(w, "    use {path};")?;
+    }
+    writeln!(w)?;
+    write!(w, "{body}")?;
+    writeln!(w, "}}")?;
     Ok(())
 }
+
+/// Formats a constant as the named rsdns constant if there is one, or converts the value.
+fn constant(uses: &mut Uses, path: &'static str, name: Option<&str>, value: u16) -> String {
+    let ty = uses.add(path);
+    match name {
+        Some(name) => format!("{ty}::{name}"),
+        None => format!("{ty}::from({value})"),
+    }
+}
+
+fn rcode(uses: &mut Uses, rcode: RCode) -> String {
+    // not all the names of response codes have constants
+    let name = [
+        RCode::NOERROR,
+        RCode::FORMERR,
+        RCode::SERVFAIL,
+        RCode::NXDOMAIN,
+        RCode::NOTIMP,
+        RCode::REFUSED,
+        RCode::BADVERS,
+    ]
+    .contains(&rcode)
+    .then(|| rcode.name());
+    constant(uses, "rsdns::message::RCode", name, rcode.value())
+}
+
+fn rtype(uses: &mut Uses, rtype: Type) -> String {
+    let name = rtype.to_string();
+    let known = name != format!("TYPE{}", rtype.value());
+    constant(
+        uses,
+        "rsdns::records::Type",
+        known.then_some(&name),
+        rtype.value(),
+    )
+}
+
+fn rclass(uses: &mut Uses, rclass: Class) -> String {
+    let name = rclass.to_string();
+    let known = name != format!("CLASS{}", rclass.value());
+    constant(
+        uses,
+        "rsdns::records::Class",
+        known.then_some(&name),
+        rclass.value(),
+    )
+}
+
+fn num<T: Display>(n: &T, _: &mut Uses) -> String {
+    n.to_string()
+}
+
+fn dn(n: &Name, _: &mut Uses) -> String {
+    format!("{:?}", n.as_str())
+}
+
+fn text(t: &[u8], _: &mut Uses) -> String {
+    byte_str(t)
+}
+
+fn ipv4(a: &Ipv4Addr, uses: &mut Uses) -> String {
+    let [o1, o2, o3, o4] = a.octets();
+    format!(
+        "{}::new({o1}, {o2}, {o3}, {o4})",
+        uses.add("std::net::Ipv4Addr")
+    )
+}
+
+fn ipv6(a: &Ipv6Addr, uses: &mut Uses) -> String {
+    let ty = uses.add("std::net::Ipv6Addr");
+    format!("{:?}.parse::<{ty}>().unwrap()", a.to_string())
+}
+
+/// Formats bytes as a byte string literal.
+fn byte_str(bytes: &[u8]) -> String {
+    let mut s = String::from("b\"");
+    for b in bytes {
+        match b {
+            b'"' => s.push_str("\\\""),
+            b'\\' => s.push_str("\\\\"),
+            b if is_ascii_printable(b) => s.push(*b as char),
+            b => s.push_str(&format!("\\x{b:02x}")),
+        }
+    }
+    s.push('"');
+    s
+}
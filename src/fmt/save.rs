@@ -1,15 +1,26 @@
-use anyhow::Result;
+use crate::{args::Args, fmt::Exchange};
+use anyhow::{Context, Result, bail};
 use base64::{Engine as _, engine::general_purpose::STANDARD as Base64Engine};
 use chrono::{DateTime, NaiveDateTime, Utc};
-use rsdns::records::Type;
+use rsdns::{
+    clients::{ClientConfig, EDns, ProtocolStrategy, Recursion},
+    records::{Class, Type},
+};
 use serde::{Deserialize, Serialize};
 use std::{
-    fs::OpenOptions,
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
     net::SocketAddr,
     str::FromStr,
     time::{Duration, SystemTime},
 };
 
+/// The version of the save format written by this build.
+///
+/// Files saved before the format was versioned hold a bare array of messages
+/// and are read as version 0.
+pub const FORMAT_VERSION: u32 = 2;
+
 #[derive(Serialize, Deserialize, Debug, Copy, Clone)]
 pub struct EncodedTime {
     pub secs: i64,
@@ -63,50 +74,391 @@ impl From<EncodedDuration> for Duration {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EncodedEdns {
+    pub version: u8,
+    pub udp_payload_size: u16,
+}
+
+impl EncodedEdns {
+    /// Returns the EDNS settings of the queries sent with `config`.
+    fn from_config(config: &ClientConfig) -> Option<EncodedEdns> {
+        match config.edns() {
+            EDns::On {
+                version,
+                udp_payload_size,
+            } => {
+                // rsdns limits the advertised size to its buffer; size 0 stands for the caller's buffer
+                let udp_payload_size = match config.buffer_size() {
+                    0 => udp_payload_size,
+                    size => udp_payload_size.min(size.min(u16::MAX as usize) as u16),
+                };
+                Some(EncodedEdns {
+                    version,
+                    udp_payload_size,
+                })
+            }
+            EDns::Off => None,
+        }
+    }
+}
+
+/// The effective client configuration of a capture.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EncodedConfig {
+    /// `udp`, `tcp` or `notcp`, as set by `+udp`, `+tcp` and `+notcp`.
+    pub protocol_strategy: String,
+    pub recursion: bool,
+    pub edns: Option<EncodedEdns>,
+    pub buffer_size: usize,
+    pub query_lifetime: EncodedDuration,
+    pub query_timeout: Option<EncodedDuration>,
+    pub bind_addr: String,
+    pub bind_device: Option<String>,
+}
+
+impl From<&ClientConfig> for EncodedConfig {
+    fn from(config: &ClientConfig) -> Self {
+        #[cfg(all(target_os = "linux", feature = "net-tokio", feature = "socket2"))]
+        let bind_device = config.bind_device().map(|d| d.to_string());
+        #[cfg(not(all(target_os = "linux", feature = "net-tokio", feature = "socket2")))]
+        let bind_device = None;
+
+        Self {
+            protocol_strategy: match config.protocol_strategy() {
+                ProtocolStrategy::Udp => "udp",
+                ProtocolStrategy::Tcp => "tcp",
+                ProtocolStrategy::NoTcp => "notcp",
+            }
+            .to_string(),
+            recursion: config.recursion() == Recursion::On,
+            edns: EncodedEdns::from_config(config),
+            buffer_size: config.buffer_size(),
+            query_lifetime: config.query_lifetime().into(),
+            query_timeout: config.query_timeout().map(EncodedDuration::from),
+            bind_addr: config.bind_addr().to_string(),
+            bind_device,
+        }
+    }
+}
+
+/// How a single message was exchanged.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EncodedProtocol {
+    /// `udp` or `tcp`.
+    pub transport: String,
+    /// Whether the transport is a guess rather than observed. The Udp protocol strategy
+    /// retries truncated responses over TCP without telling which transport the final
+    /// response came over, so a response larger than the advertised UDP payload size
+    /// is taken to be received over TCP, and any other over UDP.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub transport_inferred: bool,
+    pub edns: Option<EncodedEdns>,
+    pub recursion: bool,
+    pub qclass: String,
+    pub source: Option<String>,
+}
+
+impl EncodedProtocol {
+    pub fn new(config: &ClientConfig, source: Option<SocketAddr>, msg: &[u8]) -> Self {
+        let edns = EncodedEdns::from_config(config);
+        let udp_limit = edns.map_or(512, |e| e.udp_payload_size.max(512) as usize);
+        let (tcp, transport_inferred) = match config.protocol_strategy() {
+            ProtocolStrategy::Udp => (msg.len() > udp_limit, true),
+            ProtocolStrategy::Tcp => (true, false),
+            ProtocolStrategy::NoTcp => (false, false),
+        };
+        Self {
+            transport: if tcp { "tcp" } else { "udp" }.to_string(),
+            transport_inferred,
+            edns,
+            recursion: config.recursion() == Recursion::On,
+            qclass: Class::IN.to_string(),
+            source: source.map(|s| s.to_string()),
+        }
+    }
+}
+
+/// The client settings a saved message was exchanged with, `None` where not recorded.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Settings {
+    pub protocol_strategy: Option<ProtocolStrategy>,
+    pub recursion: Option<Recursion>,
+    pub edns: Option<EDns>,
+}
+
+impl Settings {
+    /// Collects the settings of a message from its own protocol info, and from the
+    /// configuration of the run that saved it.
+    pub fn recorded(
+        config: Option<&EncodedConfig>,
+        protocol: Option<&EncodedProtocol>,
+    ) -> Settings {
+        let protocol_strategy = config.and_then(|c| match c.protocol_strategy.as_str() {
+            "udp" => Some(ProtocolStrategy::Udp),
+            "tcp" => Some(ProtocolStrategy::Tcp),
+            "notcp" => Some(ProtocolStrategy::NoTcp),
+            _ => None,
+        });
+        let recursion = protocol
+            .map(|p| p.recursion)
+            .or(config.map(|c| c.recursion))
+            .map(|on| if on { Recursion::On } else { Recursion::Off });
+        let edns = protocol
+            .map(|p| p.edns)
+            .or(config.map(|c| c.edns))
+            .map(|edns| match edns {
+                Some(e) => EDns::On {
+                    version: e.version,
+                    udp_payload_size: e.udp_payload_size,
+                },
+                None => EDns::Off,
+            });
+        Settings {
+            protocol_strategy,
+            recursion,
+            edns,
+        }
+    }
+}
+
+/// The program versions that produced a capture.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Generator {
+    pub ch4: String,
+    pub rsdns: String,
+}
+
+impl Generator {
+    fn current() -> Generator {
+        Generator {
+            ch4: env!("CH4_VERSION").to_string(),
+            rsdns: Args::find_rsdns_version().to_string(),
+        }
+    }
+}
+
+/// A run of ch4 saved with `--save`: the circumstances of the run and its messages.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Capture {
+    pub version: u32,
+    pub generator: Option<Generator>,
+    pub cmd_line: Option<String>,
+    pub config: Option<EncodedConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub messages: Vec<EncodedMessage>,
+}
+
+impl Capture {
+    pub fn new(args: &Args) -> Capture {
+        Capture {
+            version: FORMAT_VERSION,
+            generator: Some(Generator::current()),
+            cmd_line: Some(args.cmd_line()),
+            config: Some(EncodedConfig::from(&args.config)),
+            messages: Vec::new(),
+        }
+    }
+
+    /// Creates a capture of messages ch4 didn't exchange itself, e.g. imported ones.
+    pub fn foreign(cmd_line: Option<String>) -> Capture {
+        Capture {
+            version: FORMAT_VERSION,
+            generator: Some(Generator::current()),
+            cmd_line,
+            config: None,
+            messages: Vec::new(),
+        }
+    }
+
+    /// Writes the capture in the streaming layout: a header line and a message per line.
+    pub fn write_ndjson<W: Write>(&self, w: &mut W) -> Result<()> {
+        let header = Capture {
+            version: self.version,
+            generator: self.generator.clone(),
+            cmd_line: self.cmd_line.clone(),
+            config: self.config.clone(),
+            messages: Vec::new(),
+        };
+        write_line(w, &header)?;
+        for em in self.messages.iter() {
+            write_line(w, em)?;
+        }
+        Ok(())
+    }
+
+    fn bare(messages: Vec<EncodedMessage>) -> Capture {
+        Capture {
+            version: 0,
+            generator: None,
+            cmd_line: None,
+            config: None,
+            messages,
+        }
+    }
+
+    fn check_version(&self, path: &str) -> Result<()> {
+        if self.version > FORMAT_VERSION {
+            bail!(
+                "{path}: unsupported save format version {}, this build reads up to {FORMAT_VERSION}",
+                self.version
+            );
+        }
+        Ok(())
+    }
+
+    /// Loads the captures of a file in any of the layouts written so far:
+    /// a bare array of messages (version 0), a single JSON envelope (version 1),
+    /// or a header line followed by a message per line, repeated for every
+    /// appended run (version 2).
+    ///
+    /// A partial last line, left by an interrupted run, is ignored.
+    pub fn load_all(path: &str) -> Result<Vec<Capture>> {
+        let text = std::fs::read_to_string(path).with_context(|| path.to_string())?;
+
+        let first = text.lines().find(|l| !l.trim().is_empty()).unwrap_or("");
+        let streamed = matches!(
+            serde_json::from_str::<serde_json::Value>(first),
+            Ok(serde_json::Value::Object(ref o)) if !o.contains_key("messages")
+        );
+        if !streamed {
+            let v: serde_json::Value =
+                serde_json::from_str(&text).with_context(|| path.to_string())?;
+            let capture = if v.is_array() {
+                Capture::bare(serde_json::from_value(v)?)
+            } else {
+                serde_json::from_value(v).with_context(|| path.to_string())?
+            };
+            capture.check_version(path)?;
+            return Ok(vec![capture]);
+        }
+
+        let mut captures: Vec<Capture> = Vec::new();
+        let lines: Vec<&str> = text.split_inclusive('\n').collect();
+        for (i, line) in lines.iter().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let v: serde_json::Value = match serde_json::from_str(line) {
+                Ok(v) => v,
+                Err(_) if i == lines.len() - 1 && !line.ends_with('\n') => break,
+                Err(e) => bail!("{path}: line {}: {e}", i + 1),
+            };
+            if v.get("data").is_some() {
+                let em =
+                    serde_json::from_value(v).with_context(|| format!("{path}: line {}", i + 1))?;
+                match captures.last_mut() {
+                    Some(c) => c.messages.push(em),
+                    None => captures.push(Capture::bare(vec![em])),
+                }
+            } else {
+                let capture: Capture =
+                    serde_json::from_value(v).with_context(|| format!("{path}: line {}", i + 1))?;
+                capture.check_version(path)?;
+                captures.push(capture);
+            }
+        }
+        Ok(captures)
+    }
+}
+
+/// Writes messages to a `--save` file as they are received.
+///
+/// The file holds a header line with the [`Capture`] of the run, followed by a message
+/// per line. Every line is written at once, so an interrupted run leaves at most a
+/// partial last line, which readers ignore.
+pub struct Writer {
+    file: File,
+}
+
+impl Writer {
+    /// Creates the file, or appends a new run to it if `append` is set.
+    pub fn open(args: &Args, path: &str, append: bool) -> Result<Writer> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(!append)
+            .open(path)
+            .with_context(|| path.to_string())?;
+
+        if append {
+            let mut text = String::new();
+            file.read_to_string(&mut text)
+                .with_context(|| path.to_string())?;
+            if !text.trim().is_empty() {
+                let first = text.lines().next().unwrap_or("");
+                if serde_json::from_str::<Capture>(first).is_err() {
+                    bail!(
+                        "{path}: cannot append to a file not saved by --save in the streaming format"
+                    );
+                }
+                // drop a partial last line left by an interrupted run
+                let len = text.rfind('\n').map_or(0, |i| i + 1);
+                file.set_len(len as u64)?;
+            }
+            file.seek(SeekFrom::End(0))?;
+        }
+
+        let mut writer = Writer { file };
+        writer.write_line(&Capture::new(args))?;
+        Ok(writer)
+    }
+
+    pub fn write(&mut self, em: &EncodedMessage) -> Result<()> {
+        self.write_line(em)
+    }
+
+    fn write_line<T: Serialize>(&mut self, v: &T) -> Result<()> {
+        write_line(&mut self.file, v)
+    }
+}
+
+/// Writes a value as a single JSON line, at once.
+fn write_line<W: Write, T: Serialize>(w: &mut W, v: &T) -> Result<()> {
+    let mut line = serde_json::to_vec(v)?;
+    line.push(b'\n');
+    w.write_all(&line)?;
+    w.flush()?;
+    Ok(())
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct EncodedMessage {
     pub data: String,
+    pub query: Option<String>,
+    /// Whether the query was rebuilt from the client configuration and the response id,
+    /// as rsdns doesn't expose the message it sends, rather than captured.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub query_reconstructed: bool,
     pub qname: Option<String>,
     pub qtype: Option<String>,
     pub nameserver: Option<String>,
     pub timestamp: Option<EncodedTime>,
     pub duration: Option<EncodedDuration>,
+    pub protocol: Option<EncodedProtocol>,
 }
 
 impl EncodedMessage {
-    pub fn encode(
-        msg: &[u8],
-        qname: Option<&str>,
-        qtype: Option<Type>,
-        nameserver: Option<SocketAddr>,
-        ts: Option<SystemTime>,
-        elapsed: Option<Duration>,
-    ) -> Result<serde_json::Value> {
-        let res = Self {
-            data: Base64Engine.encode(msg),
-            qname: qname.map(|s| s.to_string()),
-            qtype: qtype.map(|t| t.to_string()),
-            nameserver: nameserver.map(|ns| ns.to_string()),
-            timestamp: ts.map(EncodedTime::from),
-            duration: elapsed.map(EncodedDuration::from),
-        };
-        Ok(serde_json::to_value(res)?)
-    }
-
-    pub fn save_all(v: &[serde_json::Value], path: &str) -> Result<()> {
-        let f = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(path)?;
-        serde_json::to_writer_pretty(f, v)?;
-        Ok(())
+    pub fn encode(ex: &Exchange) -> Self {
+        Self {
+            data: Base64Engine.encode(ex.msg),
+            query: ex.query.map(|q| Base64Engine.encode(q)),
+            query_reconstructed: ex.query.is_some() && ex.query_reconstructed,
+            qname: ex.qname.map(|s| s.to_string()),
+            qtype: ex.qtype.map(|t| t.to_string()),
+            nameserver: ex.ns.map(|ns| ns.to_string()),
+            timestamp: ex.ts.map(EncodedTime::from),
+            duration: ex.elapsed.map(EncodedDuration::from),
+            protocol: ex.protocol.cloned(),
+        }
     }
 
     pub fn load_all(path: &str) -> Result<Vec<EncodedMessage>> {
-        let f = OpenOptions::new().read(true).open(path)?;
-        let v: Vec<EncodedMessage> = serde_json::from_reader(f)?;
-        Ok(v)
+        Ok(Capture::load_all(path)?
+            .into_iter()
+            .flat_map(|c| c.messages)
+            .collect())
     }
 
     pub fn qname(&self) -> Option<&str> {
@@ -135,6 +487,12 @@ impl EncodedMessage {
         Base64Engine.decode(&self.data).unwrap_or_default()
     }
 
+    pub fn query(&self) -> Option<Vec<u8>> {
+        self.query
+            .as_ref()
+            .and_then(|q| Base64Engine.decode(q).ok())
+    }
+
     pub fn time(&self) -> Option<SystemTime> {
         self.timestamp.map(|et| et.into())
     }
@@ -142,4 +500,8 @@ impl EncodedMessage {
     pub fn elapsed(&self) -> Option<Duration> {
         self.duration.map(|d| d.into())
     }
+
+    pub fn protocol(&self) -> Option<&EncodedProtocol> {
+        self.protocol.as_ref()
+    }
 }
@@ -0,0 +1,97 @@
+use crate::fmt::{Exchange, rdata::fmt_record_data_or_generic};
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use rsdns::{
+    message::{RCode, RecordsSection, reader::MessageReader},
+    names::InlineName,
+    records::Type,
+};
+use std::fmt::Write;
+
+/// A compact digest of a response: the response code and the answer records.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Summary {
+    pub rcode: RCode,
+    pub answers: Vec<String>,
+    pub min_ttl: Option<u32>,
+}
+
+impl Summary {
+    pub fn from_msg(msg: &[u8]) -> Result<Summary> {
+        let mut mr = MessageReader::new(msg)?;
+        let header = mr.header()?;
+        let mut rcode = header.flags.response_code();
+        let mut qtype = None;
+        let mut answers = Vec::new();
+        let mut min_ttl: Option<u32> = None;
+
+        while mr.has_questions() {
+            let q = mr.question()?;
+            qtype.get_or_insert(q.qtype);
+        }
+
+        while mr.has_records() {
+            let rec_header = mr.record_header::<InlineName>()?;
+            let marker = rec_header.marker();
+
+            if rec_header.section() != RecordsSection::Answer {
+                if rec_header.rtype() == Type::OPT {
+                    let opt = mr.opt_record(marker)?;
+                    rcode = RCode::extended(rcode, opt.rcode_extension());
+                } else {
+                    mr.skip_record_data(marker)?;
+                }
+                continue;
+            }
+
+            let mut answer = String::new();
+            if Some(rec_header.rtype()) != qtype {
+                write!(&mut answer, "{} ", rec_header.rtype())?;
+            }
+            fmt_record_data_or_generic(&mut answer, &mut mr, marker)?;
+            answers.push(answer);
+            min_ttl = Some(min_ttl.map_or(rec_header.ttl(), |t| t.min(rec_header.ttl())));
+        }
+
+        answers.sort();
+
+        Ok(Summary {
+            rcode,
+            answers,
+            min_ttl,
+        })
+    }
+
+    pub fn same_answer(&self, other: &Summary) -> bool {
+        self.rcode == other.rcode && self.answers == other.answers
+    }
+}
+
+/// Formats a line with the time, nameserver, question and digest of a message.
+pub fn fmt_line<W: Write>(w: &mut W, ex: &Exchange, summary: &Summary) -> Result<()> {
+    write!(w, ";; ")?;
+    if let Some(ts) = ex.ts {
+        let datetime: DateTime<Local> = DateTime::from(ts);
+        write!(w, "{} ", datetime.format("%Y-%m-%d %H:%M:%S"))?;
+    }
+    if let Some(ns) = ex.ns {
+        write!(w, "{ns} ")?;
+    }
+    write!(w, "{}", ex.qname.unwrap_or("-"))?;
+    if let Some(qtype) = ex.qtype {
+        write!(w, " {qtype}")?;
+    }
+    write!(w, ": status: {}, answer: ", summary.rcode)?;
+    if summary.answers.is_empty() {
+        write!(w, "-")?;
+    } else {
+        write!(w, "{}", summary.answers.join(", "))?;
+    }
+    if let Some(ttl) = summary.min_ttl {
+        write!(w, ", min ttl: {ttl}")?;
+    }
+    if let Some(elapsed) = ex.elapsed {
+        write!(w, ", time: {elapsed:?}")?;
+    }
+    Ok(())
+}
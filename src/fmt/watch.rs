@@ -0,0 +1,109 @@
+use crate::fmt::summary::Summary;
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    time::{Duration, SystemTime},
+};
+
+#[derive(Debug, Default)]
+struct State {
+    iteration: u64,
+    summary: Option<Summary>,
+}
+
+/// Tracks the last seen answer of every watched question.
+#[derive(Debug, Default)]
+pub struct Watch {
+    states: HashMap<String, State>,
+}
+
+/// The outcome of a single watch iteration.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Change {
+    First,
+    Same,
+    Changed,
+}
+
+impl Watch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a new summary for `key` and returns the iteration number and the change status.
+    pub fn update(&mut self, key: &str, summary: Summary) -> (u64, Change) {
+        let state = self.states.entry(key.to_string()).or_default();
+        let change = match state.summary {
+            None => Change::First,
+            Some(ref prev) if prev.same_answer(&summary) => Change::Same,
+            Some(_) => Change::Changed,
+        };
+        state.iteration += 1;
+        state.summary = Some(summary);
+        (state.iteration, change)
+    }
+
+    /// Counts a failed iteration for `key`, keeping the last seen answer intact.
+    pub fn fail(&mut self, key: &str) -> u64 {
+        let state = self.states.entry(key.to_string()).or_default();
+        state.iteration += 1;
+        state.iteration
+    }
+}
+
+pub fn fmt_line<W: Write>(
+    w: &mut W,
+    iteration: u64,
+    key: &str,
+    summary: &Summary,
+    ts: Option<SystemTime>,
+    elapsed: Option<Duration>,
+    change: Change,
+) -> Result<()> {
+    fmt_prefix(w, iteration, key, ts)?;
+    write!(w, "status: {}, answer: ", summary.rcode)?;
+    if summary.answers.is_empty() {
+        write!(w, "-")?;
+    } else {
+        write!(w, "{}", summary.answers.join(", "))?;
+    }
+    if let Some(ttl) = summary.min_ttl {
+        write!(w, ", min ttl: {ttl}")?;
+    }
+    if let Some(elapsed) = elapsed {
+        write!(w, ", time: {elapsed:?}")?;
+    }
+    if change == Change::Changed {
+        write!(w, " <<>> CHANGED")?;
+    }
+    Ok(())
+}
+
+pub fn fmt_error_line<W: Write>(
+    w: &mut W,
+    iteration: u64,
+    key: &str,
+    ts: Option<SystemTime>,
+    err: &anyhow::Error,
+) -> Result<()> {
+    fmt_prefix(w, iteration, key, ts)?;
+    write!(w, "error: {err:#}")?;
+    Ok(())
+}
+
+fn fmt_prefix<W: Write>(
+    w: &mut W,
+    iteration: u64,
+    key: &str,
+    ts: Option<SystemTime>,
+) -> Result<()> {
+    write!(w, ";; ")?;
+    if let Some(ts) = ts {
+        let datetime: DateTime<Local> = DateTime::from(ts);
+        write!(w, "{} ", datetime.format("%H:%M:%S"))?;
+    }
+    write!(w, "#{iteration} {key}: ")?;
+    Ok(())
+}
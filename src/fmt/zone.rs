@@ -1,19 +1,16 @@
 use crate::{
     args::Args,
-    fmt::rdata::{RDataFmt, RDataFormatter},
+    fmt::{Exchange, Reported, color, dissect, multiline, rdata::fmt_record_data},
+    serve::wire::flag_names,
 };
 use anyhow::{Result, bail};
 use chrono::{DateTime, Local};
 use rsdns::{
     message::{Header, RCode, RecordsSection, reader::MessageReader},
     names::InlineName,
-    records::{Opt, Type, data::*},
-};
-use std::{
-    fmt::Write,
-    net::SocketAddr,
-    time::{Duration, SystemTime},
+    records::{Opt, Type},
 };
+use std::fmt::Write;
 
 const DOMAIN_NAME_WIDTH: usize = 24;
 const QCLASS_WIDTH: usize = 7;
@@ -32,10 +29,7 @@ struct Sizes {
 #[allow(dead_code)]
 pub struct Output<'a, 'b> {
     args: &'a Args,
-    msg: &'b [u8],
-    ns: Option<SocketAddr>,
-    ts: Option<SystemTime>,
-    elapsed: Option<Duration>,
+    ex: Exchange<'b>,
     sizes: Sizes,
     opt: Option<Opt>,
 }
@@ -49,28 +43,46 @@ macro_rules! fmt_size {
 }
 
 impl<'a, 'b> Output<'a, 'b> {
-    pub fn new(
-        args: &'a Args,
-        msg: &'b [u8],
-        ns: Option<SocketAddr>,
-        ts: Option<SystemTime>,
-        elapsed: Option<Duration>,
-    ) -> Result<Self> {
-        let (sizes, opt) = Self::scan_message(msg)?;
+    pub fn new(args: &'a Args, ex: &Exchange<'b>) -> Result<Self> {
+        let (mut sizes, opt) = Self::scan_message(ex.msg, args)?;
+        if !args.display.ttlid {
+            sizes.ttl = 0;
+        }
+        if !args.display.class {
+            sizes.rclass = 0;
+        }
         Ok(Self {
             args,
-            msg,
-            ns,
-            ts,
-            elapsed,
+            ex: *ex,
             sizes,
             opt,
         })
     }
 
-    fn scan_message(msg: &[u8]) -> Result<(Sizes, Option<Opt>)> {
+    /// Measures the column widths. With `+besteffort`, the records up to a parse
+    /// failure are measured.
+    fn scan_message(msg: &[u8], args: &Args) -> Result<(Sizes, Option<Opt>)> {
         let mut sizes = Sizes::default();
         let mut opt = None;
+        let res = Self::scan_records(msg, args, &mut sizes, &mut opt);
+        if !args.best_effort {
+            res?;
+        }
+
+        sizes.name = DOMAIN_NAME_WIDTH.max(sizes.name + 2);
+        sizes.rtype = QTYPE_WIDTH.max(sizes.rtype + 1);
+        sizes.rclass = QCLASS_WIDTH.max(sizes.rclass + 1);
+        sizes.ttl = TTL_WIDTH.max(sizes.ttl + 1);
+
+        Ok((sizes, opt))
+    }
+
+    fn scan_records(
+        msg: &[u8],
+        args: &Args,
+        sizes: &mut Sizes,
+        opt: &mut Option<Opt>,
+    ) -> Result<()> {
         let mut buf = String::new();
         let mut mr = MessageReader::new(msg)?;
         mr.header()?;
@@ -83,45 +95,127 @@ impl<'a, 'b> Output<'a, 'b> {
             let header = mr.record_header::<InlineName>()?;
 
             if header.section() == RecordsSection::Additional && header.rtype() == Type::OPT {
-                opt = Some(mr.opt_record(header.marker())?);
+                *opt = Some(mr.opt_record(header.marker())?);
             } else {
                 sizes.name = sizes.name.max(header.name().len());
                 sizes.rclass = sizes.rclass.max(fmt_size!(header.rclass(), buf));
                 sizes.rtype = sizes.rtype.max(fmt_size!(header.rtype(), buf));
-                sizes.ttl = sizes.ttl.max(fmt_size!(header.ttl(), buf));
+                sizes.ttl = sizes.ttl.max(fmt_size!(Self::ttl(args, header.ttl()), buf));
                 sizes.rdlen = sizes.rdlen.max(fmt_size!(header.rdlen(), buf));
 
                 mr.skip_record_data(header.marker())?;
             }
         }
-
-        sizes.name = DOMAIN_NAME_WIDTH.max(sizes.name + 2);
-        sizes.rtype = QTYPE_WIDTH.max(sizes.rtype + 1);
-        sizes.rclass = QCLASS_WIDTH.max(sizes.rclass + 1);
-        sizes.ttl = TTL_WIDTH.max(sizes.ttl + 1);
-
-        Ok((sizes, opt))
+        Ok(())
     }
 
-    pub fn print(&self) -> Result<()> {
-        self.print_header();
+    /// Prints the message, preceded by the query it answers, if given.
+    pub fn print(&self, query: Option<&Output>) -> Result<()> {
+        let display = &self.args.display;
+        if display.cmd {
+            self.print_header();
+        }
+        if let Some(query) = query {
+            if display.comments {
+                if self.ex.query_reconstructed {
+                    println!(";; Sending (reconstructed):");
+                } else {
+                    println!(";; Sending:");
+                }
+            }
+            query.print_message()?;
+            if display.stats {
+                println!(";; QUERY SIZE: {}", query.ex.msg.len());
+            }
+            if display.comments {
+                println!();
+                println!(";; Got answer:");
+            }
+        }
         self.print_message()?;
-        self.print_footer();
+        if display.stats {
+            self.print_footer();
+        }
         Ok(())
     }
 
+    /// Prints the message. With `+besteffort`, a message failing to parse is printed up
+    /// to the failure, followed by the error and the bytes left unparsed, and the error
+    /// is returned marked as [`Reported`].
     fn print_message(&self) -> Result<()> {
-        let mut mr = MessageReader::new(self.msg)?;
+        let mut output = String::new();
+        let mut items = 0;
+        let res = self.format_message(&mut output, &mut items);
+        if res.is_ok() || self.args.best_effort {
+            if self.args.color {
+                output = color::paint(&output, self.columns());
+            }
+            print!("{output}");
+        }
+        if let Err(ref e) = res
+            && self.args.best_effort
+        {
+            if !output.is_empty() && !output.ends_with('\n') {
+                println!();
+            }
+            self.print_unparsed(items, e);
+        }
+        match res {
+            Err(e) if self.args.best_effort => Err(e.context(Reported)),
+            res => res,
+        }
+    }
+
+    fn format_message(&self, output: &mut String, items: &mut usize) -> Result<()> {
+        let comments = self.args.display.comments;
+        let mut mr = MessageReader::new(self.ex.msg)?;
         let header = mr.header()?;
-        println!("{}", self.format_response_header(&header)?);
-        if self.opt.is_some() {
-            print!("{}", self.format_opt()?);
+        if comments {
+            writeln!(output, "{}", self.format_response_header(&header)?)?;
+            if self.opt.is_some() {
+                write!(output, "{}", self.format_opt()?)?;
+            }
+        }
+        self.format_question(output, &mut mr, items)?;
+        if comments && self.args.display.question {
+            writeln!(output)?;
+        }
+        self.format_records(output, &mut mr, &header, items)?;
+        if comments {
+            writeln!(output)?;
         }
-        println!("{}", self.format_question(&mut mr)?);
-        println!("{}", self.format_records(&mut mr, &header)?);
         Ok(())
     }
 
+    fn columns(&self) -> color::Columns {
+        color::Columns {
+            name: self.sizes.name,
+            ttl: self.sizes.ttl,
+            rclass: self.sizes.rclass,
+            rtype: self.sizes.rtype,
+        }
+    }
+
+    /// Prints the parse error and the bytes following the last question or record parsed.
+    ///
+    /// The failure is located by the `+dissect` walker, as rsdns doesn't tell where it
+    /// stopped.
+    fn print_unparsed(&self, items: usize, err: &anyhow::Error) {
+        let msg = self.ex.msg;
+        let (start, broken_at) = dissect::failure_offsets(msg, items);
+        let offset = broken_at.unwrap_or(start);
+        println!(";; !! parsing stopped at offset {offset:#06x}: {err:#}");
+        println!(
+            ";; !! {} bytes left from offset {start:#06x}:",
+            msg.len() - start
+        );
+        for (i, chunk) in msg[start..].chunks(16).enumerate() {
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{b:02x}")).collect();
+            println!(";; {:04x}  {}", start + i * 16, hex.join(" "));
+        }
+        println!();
+    }
+
     fn format_response_header(&self, header: &Header) -> Result<String> {
         let mut output = String::new();
         let status = if let Some(ref o) = self.opt {
@@ -139,7 +233,7 @@ impl<'a, 'b> Output<'a, 'b> {
         writeln!(
             &mut output,
             ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
-            Self::format_flags(header),
+            flag_names(header.flags.into()).join(" "),
             header.qd_count,
             header.an_count,
             header.ns_count,
@@ -148,140 +242,150 @@ impl<'a, 'b> Output<'a, 'b> {
         Ok(output)
     }
 
-    fn format_question(&self, mr: &mut MessageReader) -> Result<String> {
-        let mut output = String::new();
-        writeln!(&mut output, ";; QUESTION SECTION:")?;
+    fn format_question(
+        &self,
+        output: &mut String,
+        mr: &mut MessageReader,
+        items: &mut usize,
+    ) -> Result<()> {
+        let display = &self.args.display;
+        if display.comments && display.question {
+            writeln!(output, ";; QUESTION SECTION:")?;
+        }
 
         while mr.has_questions() {
             let q = mr.question()?;
+            *items += 1;
+            if !display.question {
+                continue;
+            }
             write!(
-                &mut output,
-                ";{:dn_width$}{:ttl_width$}{:qc_width$}{:qt_width$}",
+                output,
+                ";{:dn_width$}{:ttl_width$}",
                 q.qname,
-                " ",
-                q.qclass,
-                q.qtype,
+                "",
                 dn_width = self.sizes.name - 1,
                 ttl_width = self.sizes.ttl,
-                qc_width = self.sizes.rclass,
-                qt_width = self.sizes.rtype,
             )?;
+            if display.class {
+                write!(
+                    output,
+                    "{:qc_width$}",
+                    q.qclass,
+                    qc_width = self.sizes.rclass
+                )?;
+            }
+            write!(output, "{:qt_width$}", q.qtype, qt_width = self.sizes.rtype)?;
+            if !display.comments {
+                writeln!(output)?;
+            }
         }
-        Ok(output)
+        Ok(())
     }
 
-    fn format_records(&self, mr: &mut MessageReader, header: &Header) -> Result<String> {
-        let mut output = String::new();
+    fn format_records(
+        &self,
+        output: &mut String,
+        mr: &mut MessageReader,
+        header: &Header,
+        items: &mut usize,
+    ) -> Result<()> {
         let mut section = None;
 
         while mr.has_records() {
-            let rec_header = mr.record_header::<InlineName>()?;
-            let sec = rec_header.section();
+            // a record failing to parse is dropped along with its section heading
+            let start = output.len();
+            if let Err(e) = self.format_record(output, mr, header, &mut section) {
+                output.truncate(start);
+                return Err(e);
+            }
+            *items += 1;
+        }
+        Ok(())
+    }
 
-            if section != Some(sec) {
-                section = Some(sec);
-                if sec != RecordsSection::Additional || self.opt.is_none() || header.ar_count > 1 {
-                    writeln!(&mut output, "\n;; {} SECTION:", sec.to_str().to_uppercase())?;
+    fn format_record(
+        &self,
+        output: &mut String,
+        mr: &mut MessageReader,
+        header: &Header,
+        section: &mut Option<RecordsSection>,
+    ) -> Result<()> {
+        let display = &self.args.display;
+        let rec_header = mr.record_header::<InlineName>()?;
+        let sec = rec_header.section();
+
+        if *section != Some(sec) {
+            *section = Some(sec);
+            if display.comments
+                && display.section(sec)
+                && (sec != RecordsSection::Additional || self.opt.is_none() || header.ar_count > 1)
+            {
+                // a blank line separates the sections, unless the header block already ends
+                // with one
+                if !output.ends_with("\n\n") {
+                    writeln!(output)?;
                 }
+                writeln!(output, ";; {} SECTION:", sec.to_str().to_uppercase())?;
             }
+        }
 
-            if sec == RecordsSection::Additional && rec_header.rtype() == Type::OPT {
-                mr.skip_record_data(rec_header.marker())?;
-                continue;
-            }
+        if !display.section(sec)
+            || (sec == RecordsSection::Additional && rec_header.rtype() == Type::OPT)
+        {
+            mr.skip_record_data(rec_header.marker())?;
+            return Ok(());
+        }
 
+        write!(
+            output,
+            "{:dn_width$}",
+            rec_header.name(),
+            dn_width = self.sizes.name
+        )?;
+        if display.ttlid {
             write!(
-                &mut output,
-                "{:dn_width$}{:<ttl_width$}{:qc_width$}{:qt_width$}",
-                rec_header.name(),
-                rec_header.ttl(),
+                output,
+                "{:<ttl_width$}",
+                Self::ttl(self.args, rec_header.ttl()),
+                ttl_width = self.sizes.ttl
+            )?;
+        }
+        if display.class {
+            write!(
+                output,
+                "{:qc_width$}",
                 rec_header.rclass(),
-                rec_header.rtype(),
-                dn_width = self.sizes.name,
-                ttl_width = self.sizes.ttl,
-                qc_width = self.sizes.rclass,
-                qt_width = self.sizes.rtype,
+                qc_width = self.sizes.rclass
             )?;
+        }
+        write!(
+            output,
+            "{:qt_width$}",
+            rec_header.rtype(),
+            qt_width = self.sizes.rtype
+        )?;
 
-            let rtype = if self.args.format.is_rfc3597() {
-                Type::ANY // use a type that forces RFC 3597 below
-            } else {
-                rec_header.rtype()
-            };
-
-            match rtype {
-                Type::A => {
-                    let a = mr.record_data::<A>(rec_header.marker())?;
-                    RDataFmt::fmt(&mut output, &a)?;
-                }
-                Type::AAAA => {
-                    let aaaa = mr.record_data::<Aaaa>(rec_header.marker())?;
-                    RDataFmt::fmt(&mut output, &aaaa)?;
-                }
-                Type::CNAME => {
-                    let cname = mr.record_data::<Cname>(rec_header.marker())?;
-                    RDataFmt::fmt(&mut output, &cname)?;
-                }
-                Type::NS => {
-                    let ns = mr.record_data::<Ns>(rec_header.marker())?;
-                    RDataFmt::fmt(&mut output, &ns)?;
-                }
-                Type::SOA => {
-                    let soa = mr.record_data::<Soa>(rec_header.marker())?;
-                    RDataFmt::fmt(&mut output, &soa)?;
-                }
-                Type::PTR => {
-                    let ptr = mr.record_data::<Ptr>(rec_header.marker())?;
-                    RDataFmt::fmt(&mut output, &ptr)?;
-                }
-                Type::MX => {
-                    let mx = mr.record_data::<Mx>(rec_header.marker())?;
-                    RDataFmt::fmt(&mut output, &mx)?;
-                }
-                Type::TXT => {
-                    let txt = mr.record_data::<Txt>(rec_header.marker())?;
-                    RDataFmt::fmt(&mut output, &txt)?;
-                }
-                Type::HINFO => {
-                    let hinfo = mr.record_data::<Hinfo>(rec_header.marker())?;
-                    RDataFmt::fmt(&mut output, &hinfo)?;
-                }
-                Type::SRV => {
-                    let srv = mr.record_data::<Srv>(rec_header.marker())?;
-                    RDataFmt::fmt(&mut output, &srv)?;
-                }
-                _ => {
-                    let bytes = mr.record_data_bytes(rec_header.marker())?;
-                    write!(&mut output, "{}", self.format_rfc_3597(bytes)?)?;
-                }
-            }
-
-            writeln!(&mut output)?;
+        let marker = rec_header.marker();
+        let indent = self.sizes.name + self.sizes.ttl + self.sizes.rclass + self.sizes.rtype;
+        let formatted = !self.args.format.is_rfc3597()
+            && ((self.args.multiline && multiline::fmt_record_data(output, mr, marker, indent)?)
+                || fmt_record_data(output, mr, marker)?);
+        if !formatted {
+            let bytes = mr.record_data_bytes(marker)?;
+            write!(output, "{}", self.format_rfc_3597(bytes)?)?;
         }
 
-        Ok(output)
+        writeln!(output)?;
+        Ok(())
     }
 
-    fn format_flags(header: &Header) -> String {
-        let mut flags_str = Vec::new();
-
-        if header.flags.message_type().is_response() {
-            flags_str.push("qr");
-        }
-        if header.flags.authoritative_answer() {
-            flags_str.push("aa");
-        }
-        if header.flags.truncated() {
-            flags_str.push("tc");
-        }
-        if header.flags.recursion_desired() {
-            flags_str.push("rd");
-        }
-        if header.flags.recursion_available() {
-            flags_str.push("ra");
+    fn ttl(args: &Args, ttl: u32) -> String {
+        if args.ttl_units {
+            multiline::ttl_units(ttl)
+        } else {
+            ttl.to_string()
         }
-
-        flags_str.join(" ")
     }
 
     fn format_rfc_3597(&self, d: &[u8]) -> Result<String> {
@@ -308,7 +412,9 @@ impl<'a, 'b> Output<'a, 'b> {
         println!(
             "; <<>> ch4 {} <<>> {}",
             env!("CH4_VERSION"),
-            self.args.cmd_line()
+            self.ex
+                .cmd_line
+                .map_or_else(|| self.args.cmd_line(), |c| c.to_string())
         );
     }
 
@@ -335,16 +441,19 @@ impl<'a, 'b> Output<'a, 'b> {
     }
 
     fn print_footer(&self) {
-        if let Some(elapsed) = self.elapsed {
+        if let Some(elapsed) = self.ex.elapsed {
             println!(";; Query time: {elapsed:?}");
         }
-        if let Some(ns) = self.ns {
+        if let Some(ns) = self.ex.ns {
             println!(";; SERVER: {ns}");
         }
-        if let Some(ts) = self.ts {
+        if let Some(source) = self.ex.protocol.and_then(|p| p.source.as_deref()) {
+            println!(";; SOURCE: {source}");
+        }
+        if let Some(ts) = self.ex.ts {
             let datetime: DateTime<Local> = DateTime::from(ts);
             println!(";; WHEN: {}", datetime.to_rfc2822());
         }
-        println!(";; MSG SIZE rcvd: {}", self.msg.len());
+        println!(";; MSG SIZE rcvd: {}", self.ex.msg.len());
     }
 }
@@ -0,0 +1,229 @@
+use crate::fmt::rdata::fmt_record_data_or_generic;
+use anyhow::Result;
+use rsdns::{
+    message::{RecordsSection, reader::MessageReader},
+    names::InlineName,
+    records::{Class, Type},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Write,
+};
+
+/// Identifies an RRset, sorted by owner name in canonical order, then by type.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Key {
+    /// The owner name labels, from the root.
+    labels: Vec<String>,
+    class: Class,
+    /// SOA and NS records go first, as in zone files written by hand.
+    rank: u8,
+    rtype: Type,
+}
+
+#[derive(Debug, Default)]
+struct RRset {
+    ttl: u32,
+    rdata: BTreeSet<String>,
+}
+
+/// Collects the records of responses and prints them as an RFC 1035 master file.
+///
+/// Records are deduplicated into RRsets. An RRset seen with different TTLs, e.g. in
+/// responses of a caching resolver, gets the greatest one.
+#[derive(Debug, Default)]
+pub struct ZoneFile {
+    rrsets: BTreeMap<Key, RRset>,
+}
+
+impl ZoneFile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the records of the answer section, except OPT and other meta types.
+    ///
+    /// The authority and additional sections are left out: the SOA of negative
+    /// responses, delegations and glue of referrals belong to other zones, or
+    /// to other names than the ones queried.
+    pub fn add(&mut self, msg: &[u8]) -> Result<()> {
+        let mut mr = MessageReader::new(msg)?;
+        mr.header()?;
+        while mr.has_questions() {
+            mr.question()?;
+        }
+
+        while mr.has_records() {
+            let rec_header = mr.record_header::<InlineName>()?;
+            if rec_header.section() != RecordsSection::Answer {
+                break;
+            }
+            let marker = rec_header.marker();
+            if rec_header.rtype().is_meta_type() || rec_header.rtype() == Type::OPT {
+                mr.skip_record_data(marker)?;
+                continue;
+            }
+
+            let mut rdata = String::new();
+            fmt_record_data_or_generic(&mut rdata, &mut mr, marker)?;
+
+            let rtype = rec_header.rtype();
+            let key = Key {
+                labels: labels(rec_header.name().as_str()),
+                class: rec_header.rclass(),
+                rank: match rtype {
+                    Type::SOA => 0,
+                    Type::NS => 1,
+                    _ => 2,
+                },
+                rtype,
+            };
+            let rrset = self.rrsets.entry(key).or_default();
+            rrset.ttl = rrset.ttl.max(rec_header.ttl());
+            rrset.rdata.insert(rdata);
+        }
+        Ok(())
+    }
+
+    /// Formats the collected records, nothing if there are none.
+    pub fn fmt<W: Write>(&self, w: &mut W) -> Result<()> {
+        if self.rrsets.is_empty() {
+            return Ok(());
+        }
+        let origin = self.origin();
+        let default_ttl = self.default_ttl();
+
+        let lines: Vec<(String, String, &Key, &str)> = self
+            .rrsets
+            .iter()
+            .flat_map(|(key, rrset)| {
+                let owner = relative(&key.labels, &origin);
+                let ttl = if rrset.ttl == default_ttl {
+                    String::new()
+                } else {
+                    rrset.ttl.to_string()
+                };
+                rrset
+                    .rdata
+                    .iter()
+                    .map(move |rdata| (owner.clone(), ttl.clone(), key, rdata.as_str()))
+            })
+            .collect();
+        let owner_width = lines.iter().map(|l| l.0.len()).max().unwrap_or(0);
+        let ttl_width = lines.iter().map(|l| l.1.len()).max().unwrap_or(0);
+
+        writeln!(w, "$ORIGIN {}", absolute(&origin))?;
+        writeln!(w, "$TTL {default_ttl}")?;
+        for (owner, ttl, key, rdata) in lines {
+            write!(w, "{owner:<owner_width$} ")?;
+            if ttl_width > 0 {
+                write!(w, "{ttl:<ttl_width$} ")?;
+            }
+            writeln!(
+                w,
+                "{} {} {}",
+                key.class,
+                key.rtype,
+                relative_rdata(key.rtype, rdata, &origin)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Returns the zone apex: the owner of the SOA record if there is only one,
+    /// or the longest suffix common to all owner names.
+    fn origin(&self) -> Vec<String> {
+        let mut soa = self
+            .rrsets
+            .keys()
+            .filter(|k| k.rtype == Type::SOA)
+            .map(|k| &k.labels);
+        if let (Some(apex), None) = (soa.next(), soa.next()) {
+            return apex.clone();
+        }
+
+        let mut keys = self.rrsets.keys();
+        let mut origin = keys.next().map(|k| k.labels.clone()).unwrap_or_default();
+        for key in keys {
+            let common = origin
+                .iter()
+                .zip(key.labels.iter())
+                .take_while(|(a, b)| a == b)
+                .count();
+            origin.truncate(common);
+        }
+        origin
+    }
+
+    /// Returns the most frequent RRset TTL, the lowest one on a tie.
+    fn default_ttl(&self) -> u32 {
+        let mut counts: HashMap<u32, usize> = HashMap::new();
+        for rrset in self.rrsets.values() {
+            *counts.entry(rrset.ttl).or_default() += 1;
+        }
+        counts
+            .into_iter()
+            .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))
+            .map_or(0, |(ttl, _)| ttl)
+    }
+}
+
+/// Splits a domain name into lowercase labels, starting from the root.
+fn labels(name: &str) -> Vec<String> {
+    name.trim_end_matches('.')
+        .split('.')
+        .filter(|l| !l.is_empty())
+        .rev()
+        .map(|l| l.to_ascii_lowercase())
+        .collect()
+}
+
+fn absolute(labels: &[String]) -> String {
+    if labels.is_empty() {
+        return ".".to_string();
+    }
+    let mut name: Vec<&str> = labels.iter().rev().map(|l| l.as_str()).collect();
+    name.push("");
+    name.join(".")
+}
+
+/// Formats a name relative to the origin: `@` for the origin itself, or the
+/// absolute name if it is outside of the origin.
+fn relative(labels: &[String], origin: &[String]) -> String {
+    if labels == origin {
+        "@".to_string()
+    } else if labels.starts_with(origin) && !origin.is_empty() {
+        let rest: Vec<&str> = labels[origin.len()..]
+            .iter()
+            .rev()
+            .map(|l| l.as_str())
+            .collect();
+        rest.join(".")
+    } else {
+        absolute(labels)
+    }
+}
+
+/// Makes the domain names in the record data relative to the origin.
+fn relative_rdata(rtype: Type, rdata: &str, origin: &[String]) -> String {
+    // the positions of the domain names among the fields
+    let names: &[usize] = match rtype {
+        Type::NS | Type::CNAME | Type::PTR => &[0],
+        Type::MX => &[1],
+        Type::SRV => &[3],
+        Type::SOA => &[0, 1],
+        _ => return rdata.to_string(),
+    };
+    rdata
+        .split(' ')
+        .enumerate()
+        .map(|(i, field)| {
+            if names.contains(&i) {
+                relative(&labels(field), origin)
+            } else {
+                field.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
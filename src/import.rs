@@ -0,0 +1,493 @@
+use crate::{
+    args::{ImportArgs, parse_server_addr},
+    fmt::{
+        Exchange,
+        save::{Capture, EncodedEdns, EncodedMessage, EncodedProtocol},
+    },
+    serve::{
+        master,
+        name::Name,
+        wire::{FLAGS, HEADER_LEN, QR, RD},
+    },
+    status::Status,
+};
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::{DateTime, FixedOffset, NaiveDateTime, Utc};
+use rsdns::{
+    message::{OpCode, RCode},
+    records::{Class, Type},
+};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    net::SocketAddr,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+/// The EDNS option code of DNS cookies, RFC 7873.
+const COOKIE: u16 = 10;
+
+#[derive(Debug)]
+struct Edns {
+    version: u8,
+    udp_payload_size: u16,
+    dnssec_ok: bool,
+    options: Vec<(u16, Vec<u8>)>,
+}
+
+/// A message printed by dig, as read so far.
+#[derive(Debug, Default)]
+struct Message {
+    /// The line of the `->>HEADER<<-` of the message.
+    line: usize,
+    id: u16,
+    opcode: u8,
+    rcode: u16,
+    flags: u16,
+    edns: Option<Edns>,
+    question: Option<(Name, Type, Class)>,
+    /// The record lines of the answer, authority and additional sections.
+    sections: [String; 3],
+    ns: Option<SocketAddr>,
+    transport: Option<&'static str>,
+    elapsed: Option<Duration>,
+    ts: Option<SystemTime>,
+    /// The first error found in the message, which is skipped.
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    None,
+    Opt,
+    Question,
+    Records(usize),
+}
+
+impl Message {
+    fn is_response(&self) -> bool {
+        self.flags & QR != 0
+    }
+
+    /// Encodes the message in wire format. Domain names are not compressed.
+    fn encode(&self) -> Result<Vec<u8>> {
+        let mut msg = vec![0u8; HEADER_LEN];
+        msg[0..2].copy_from_slice(&self.id.to_be_bytes());
+        let flags = self.flags | ((self.opcode as u16 & 0x0F) << 11) | (self.rcode & 0x0F);
+        msg[2..4].copy_from_slice(&flags.to_be_bytes());
+
+        if let Some((ref qname, qtype, qclass)) = self.question {
+            qname.to_wire(&mut msg);
+            msg.extend_from_slice(&qtype.value().to_be_bytes());
+            msg.extend_from_slice(&qclass.value().to_be_bytes());
+            msg[4..6].copy_from_slice(&1u16.to_be_bytes());
+        }
+
+        for (i, text) in self.sections.iter().enumerate() {
+            let mut count = 0u16;
+            for r in master::parse(text, Some(Name::root()))? {
+                r.name.to_wire(&mut msg);
+                msg.extend_from_slice(&r.rtype.value().to_be_bytes());
+                msg.extend_from_slice(&r.rclass.value().to_be_bytes());
+                msg.extend_from_slice(&r.ttl.to_be_bytes());
+                msg.extend_from_slice(&(r.rdata.len() as u16).to_be_bytes());
+                msg.extend_from_slice(&r.rdata);
+                count += 1;
+            }
+            if i == 2
+                && let Some(ref edns) = self.edns
+            {
+                self.encode_opt(&mut msg, edns);
+                count += 1;
+            }
+            let offset = 6 + 2 * i;
+            msg[offset..offset + 2].copy_from_slice(&count.to_be_bytes());
+        }
+        Ok(msg)
+    }
+
+    fn encode_opt(&self, msg: &mut Vec<u8>, edns: &Edns) {
+        let ttl = ((self.rcode as u32 >> 4) << 24)
+            | ((edns.version as u32) << 16)
+            | if edns.dnssec_ok { 0x8000 } else { 0 };
+        let mut rdata = Vec::new();
+        for (code, data) in edns.options.iter() {
+            rdata.extend_from_slice(&code.to_be_bytes());
+            rdata.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            rdata.extend_from_slice(data);
+        }
+
+        Name::root().to_wire(msg);
+        msg.extend_from_slice(&Type::OPT.value().to_be_bytes());
+        msg.extend_from_slice(&edns.udp_payload_size.to_be_bytes());
+        msg.extend_from_slice(&ttl.to_be_bytes());
+        msg.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        msg.extend_from_slice(&rdata);
+    }
+}
+
+/// Reads the messages printed by dig, or by ch4 in its default output format.
+struct Reader {
+    captures: Vec<Capture>,
+    message: Option<Message>,
+    section: Section,
+    /// A query printed with `+qr`, waiting for its response.
+    query: Option<(Vec<u8>, Option<EncodedEdns>)>,
+    /// Whether ch4 printed the next query as rebuilt rather than sent.
+    query_reconstructed: bool,
+    skipped: usize,
+}
+
+impl Reader {
+    fn new() -> Reader {
+        Reader {
+            captures: Vec::new(),
+            message: None,
+            section: Section::None,
+            query: None,
+            query_reconstructed: false,
+            skipped: 0,
+        }
+    }
+
+    fn read(&mut self, text: &str) -> Result<()> {
+        for (i, line) in text.lines().enumerate() {
+            if let Err(e) = self.read_line(i + 1, line) {
+                match self.message {
+                    Some(ref mut m) => {
+                        m.error
+                            .get_or_insert_with(|| format!("line {}: {e:#}", i + 1));
+                    }
+                    None => return Err(e.context(format!("line {}", i + 1))),
+                }
+            }
+        }
+        self.finish();
+        Ok(())
+    }
+
+    fn read_line(&mut self, line_no: usize, line: &str) -> Result<()> {
+        if line.trim().is_empty() {
+            self.section = Section::None;
+            return Ok(());
+        }
+
+        if let Some(rest) = line.strip_prefix(";;") {
+            let rest = rest.trim();
+            if rest.starts_with("->>HEADER<<-") {
+                self.finish();
+                self.message = Some(parse_header(line_no, rest).unwrap_or_else(|e| Message {
+                    line: line_no,
+                    error: Some(format!("{e:#}")),
+                    ..Default::default()
+                }));
+                self.section = Section::None;
+                return Ok(());
+            }
+            if rest == "Sending (reconstructed):" {
+                self.finish();
+                self.query_reconstructed = true;
+                return Ok(());
+            }
+            let Some(m) = self.message.as_mut() else {
+                return Ok(());
+            };
+            if let Some(flags) = rest.strip_prefix("flags:") {
+                m.flags = parse_flags(flags);
+            } else if rest.starts_with("OPT PSEUDOSECTION") {
+                self.section = Section::Opt;
+            } else if rest.starts_with("QUESTION SECTION") {
+                self.section = Section::Question;
+            } else if rest.starts_with("ANSWER SECTION") {
+                self.section = Section::Records(0);
+            } else if rest.starts_with("AUTHORITY SECTION") {
+                self.section = Section::Records(1);
+            } else if rest.starts_with("ADDITIONAL SECTION") {
+                self.section = Section::Records(2);
+            } else if let Some(v) = rest.strip_prefix("Query time:") {
+                m.elapsed = parse_elapsed(v.trim());
+            } else if let Some(v) = rest.strip_prefix("SERVER:") {
+                let v = v.trim();
+                let addr = v.split(['(', ' ']).next().unwrap_or_default();
+                m.ns = parse_server_addr(addr, 53);
+                m.transport = if v.ends_with("(TCP)") {
+                    Some("tcp")
+                } else if v.ends_with("(UDP)") {
+                    Some("udp")
+                } else {
+                    None
+                };
+            } else if let Some(v) = rest.strip_prefix("WHEN:") {
+                m.ts = parse_when(v.trim());
+            }
+            return Ok(());
+        }
+
+        if let Some(rest) = line.strip_prefix(';') {
+            if rest.contains("<<>>") {
+                // the banner of a new run, e.g. "; <<>> DiG 9.18.28 <<>> @8.8.8.8 example.com"
+                self.finish();
+                self.query = None;
+                self.query_reconstructed = false;
+                let cmd_line = rest.split("<<>>").nth(2).map(|s| s.trim().to_string());
+                self.captures.push(Capture::foreign(cmd_line));
+                return Ok(());
+            }
+            let Some(m) = self.message.as_mut() else {
+                return Ok(());
+            };
+            match self.section {
+                Section::Question => m.question = Some(parse_question(rest)?),
+                Section::Opt => parse_opt_line(m, rest.trim())?,
+                _ => {}
+            }
+            return Ok(());
+        }
+
+        if let (Some(m), Section::Records(i)) = (self.message.as_mut(), self.section) {
+            m.sections[i].push_str(line);
+            m.sections[i].push('\n');
+        }
+        Ok(())
+    }
+
+    /// Encodes the message read so far, if any. Queries are kept for the next response.
+    fn finish(&mut self) {
+        let Some(m) = self.message.take() else {
+            return;
+        };
+        let msg = match m.error {
+            Some(ref e) => Err(anyhow!("{e}")),
+            None => m.encode(),
+        };
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(e) => {
+                eprintln!(";; skipped the message at line {}: {e:#}", m.line);
+                self.skipped += 1;
+                return;
+            }
+        };
+
+        let edns = m.edns.as_ref().map(|e| EncodedEdns {
+            version: e.version,
+            udp_payload_size: e.udp_payload_size,
+        });
+        if !m.is_response() {
+            self.query = Some((msg, edns));
+            return;
+        }
+
+        let query = self.query.take();
+        let query_reconstructed = std::mem::take(&mut self.query_reconstructed);
+        let (qname, qtype, qclass) = match m.question {
+            Some((ref name, qtype, qclass)) => {
+                let name = name.to_string();
+                let name = match name.strip_suffix('.') {
+                    Some(n) if !n.is_empty() => n.to_string(),
+                    _ => name,
+                };
+                (Some(name), Some(qtype), qclass)
+            }
+            None => (None, None, Class::IN),
+        };
+        let protocol = m.transport.map(|transport| EncodedProtocol {
+            transport: transport.to_string(),
+            transport_inferred: false,
+            edns: query.as_ref().and_then(|q| q.1),
+            recursion: m.flags & RD != 0,
+            qclass: qclass.to_string(),
+            source: None,
+        });
+        let em = EncodedMessage::encode(&Exchange {
+            qname: qname.as_deref(),
+            qtype,
+            query: query.as_ref().map(|q| q.0.as_slice()),
+            query_reconstructed,
+            msg: &msg,
+            ns: m.ns,
+            ts: m.ts,
+            elapsed: m.elapsed,
+            protocol: protocol.as_ref(),
+            cmd_line: None,
+        });
+
+        if self.captures.is_empty() {
+            self.captures.push(Capture::foreign(None));
+        }
+        self.captures.last_mut().unwrap().messages.push(em);
+    }
+}
+
+/// Converts the textual output of dig into the format written by `--save`.
+pub fn run(args: &ImportArgs) -> Result<Status> {
+    let text = std::fs::read_to_string(&args.dig).with_context(|| args.dig.clone())?;
+
+    let mut reader = Reader::new();
+    reader.read(&text).with_context(|| args.dig.clone())?;
+
+    let captures: Vec<Capture> = reader
+        .captures
+        .into_iter()
+        .filter(|c| !c.messages.is_empty())
+        .collect();
+    let count: usize = captures.iter().map(|c| c.messages.len()).sum();
+    if count == 0 && reader.skipped == 0 {
+        bail!("{}: no dig messages found", args.dig);
+    }
+
+    match args.output {
+        Some(ref path) => {
+            let mut w = BufWriter::new(File::create(path).with_context(|| path.clone())?);
+            for c in captures.iter() {
+                c.write_ndjson(&mut w)?;
+            }
+            w.flush()?;
+        }
+        None => {
+            let mut w = io::stdout().lock();
+            for c in captures.iter() {
+                c.write_ndjson(&mut w)?;
+            }
+        }
+    }
+    eprintln!(";; imported {count} messages from {}", args.dig);
+
+    if reader.skipped > 0 {
+        Ok(Status::Parse)
+    } else {
+        Ok(Status::Success)
+    }
+}
+
+/// Parses `->>HEADER<<- opcode: QUERY, status: NOERROR, id: 12345`.
+fn parse_header(line: usize, s: &str) -> Result<Message> {
+    let mut m = Message {
+        line,
+        ..Default::default()
+    };
+    for field in s.trim_start_matches("->>HEADER<<-").split(',') {
+        let Some((key, value)) = field.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim() {
+            "opcode" => match OpCode::from_str(value) {
+                Ok(opcode) => m.opcode = opcode.value(),
+                Err(_) => bail!("bad opcode: {value}"),
+            },
+            "status" => match RCode::from_str(value) {
+                Ok(rcode) => m.rcode = rcode.value(),
+                Err(_) => bail!("bad status: {value}"),
+            },
+            "id" => match u16::from_str(value) {
+                Ok(id) => m.id = id,
+                Err(_) => bail!("bad id: {value}"),
+            },
+            _ => {}
+        }
+    }
+    Ok(m)
+}
+
+/// Parses `flags: qr rd ra; QUERY: 1, ANSWER: 1, AUTHORITY: 0, ADDITIONAL: 1`.
+///
+/// The counts are not needed, the sections are counted as they are encoded.
+fn parse_flags(s: &str) -> u16 {
+    let names = s.split(';').next().unwrap_or_default();
+    names
+        .split_whitespace()
+        .filter_map(|f| FLAGS.iter().find(|(_, name)| *name == f))
+        .fold(0, |flags, (bit, _)| flags | bit)
+}
+
+/// Parses a question line, without the leading `;`: `example.com.  IN  A`.
+fn parse_question(s: &str) -> Result<(Name, Type, Class)> {
+    let fields: Vec<&str> = s.split_whitespace().collect();
+    let [name, class, qtype] = fields[..] else {
+        bail!("bad question: {s}");
+    };
+    let name = Name::parse(name, Some(&Name::root()))?;
+    let Ok(class) = Class::from_str(&class.to_uppercase()) else {
+        bail!("bad question class: {class}");
+    };
+    let Some(qtype) = master::parse_type(qtype) else {
+        bail!("bad question type: {qtype}");
+    };
+    Ok((name, qtype, class))
+}
+
+/// Parses a line of the OPT pseudosection, without the leading `;`.
+///
+/// Of the EDNS options, only cookies are kept.
+fn parse_opt_line(m: &mut Message, s: &str) -> Result<()> {
+    if let Some(rest) = s.strip_prefix("EDNS:") {
+        // EDNS: version: 0, flags: do; udp: 1232
+        let mut edns = Edns {
+            version: 0,
+            udp_payload_size: 512,
+            dnssec_ok: false,
+            options: Vec::new(),
+        };
+        for field in rest.split([',', ';']) {
+            let Some((key, value)) = field.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match key.trim() {
+                "version" => edns.version = value.parse().context("bad EDNS version")?,
+                "udp" => edns.udp_payload_size = value.parse().context("bad EDNS udp size")?,
+                "flags" => edns.dnssec_ok = value.split_whitespace().any(|f| f == "do"),
+                _ => {}
+            }
+        }
+        m.edns = Some(edns);
+    } else if let Some(rest) = s.strip_prefix("COOKIE:")
+        && let Some(ref mut edns) = m.edns
+    {
+        let hex = rest.split_whitespace().next().unwrap_or_default();
+        edns.options.push((COOKIE, master::decode_hex(hex)?));
+    }
+    Ok(())
+}
+
+/// Parses the query time of dig (`20 msec`, `150 usec`) or ch4 (`62.709564ms`).
+fn parse_elapsed(s: &str) -> Option<Duration> {
+    let s = s.replace(' ', "");
+    let split = s.find(|c: char| !(c.is_ascii_digit() || c == '.'))?;
+    let (value, unit) = s.split_at(split);
+    let value = f64::from_str(value).ok()?;
+    let secs = match unit {
+        "s" | "sec" => value,
+        "ms" | "msec" => value / 1e3,
+        "µs" | "us" | "usec" => value / 1e6,
+        "ns" | "nsec" => value / 1e9,
+        _ => return None,
+    };
+    Some(Duration::from_secs_f64(secs))
+}
+
+/// Parses the time of dig (`Mon Oct 14 10:00:00 UTC 2024`) or ch4 (RFC 2822).
+///
+/// Time zones given by abbreviation are only known for UTC and GMT; other times are dropped.
+fn parse_when(s: &str) -> Option<SystemTime> {
+    if let Ok(dt) = DateTime::parse_from_rfc2822(s) {
+        return Some(dt.into());
+    }
+    let fields: Vec<&str> = s.split_whitespace().collect();
+    let [_, month, day, time, tz, year] = fields[..] else {
+        return None;
+    };
+    let ndt =
+        NaiveDateTime::parse_from_str(&format!("{month} {day} {time} {year}"), "%b %d %H:%M:%S %Y")
+            .ok()?;
+    let offset = match tz {
+        "UTC" | "GMT" | "Z" => FixedOffset::east_opt(0)?,
+        tz => DateTime::parse_from_str(&format!("2000-01-01 00:00 {tz}"), "%Y-%m-%d %H:%M %z")
+            .ok()?
+            .timezone(),
+    };
+    let dt: DateTime<Utc> = ndt.and_local_timezone(offset).single()?.into();
+    Some(dt.into())
+}
@@ -1,4 +1,5 @@
 use anyhow::Result;
+use std::process::ExitCode;
 
 #[cfg(windows)]
 pub(crate) mod win;
@@ -31,27 +32,31 @@ mod std_main {
 
 pub(crate) mod args;
 pub(crate) mod fmt;
+pub(crate) mod import;
+pub(crate) mod resolvers;
+pub(crate) mod serve;
+pub(crate) mod status;
 
 cfg_if::cfg_if! {
     if #[cfg(feature = "net-tokio")] {
         #[tokio::main(flavor = "current_thread")]
-        async fn main() -> Result<()> {
-            async_main::main().await
+        async fn main() -> ExitCode {
+            status::report(async_main::main().await)
         }
     } else if #[cfg(feature = "net-async-std")] {
         #[async_std::main]
-        async fn main() -> Result<()> {
-            async_main::main().await
+        async fn main() -> ExitCode {
+            status::report(async_main::main().await)
         }
     } else if #[cfg(feature = "net-smol")] {
-        fn main() -> Result<()> {
-            smol::block_on(async {
+        fn main() -> ExitCode {
+            status::report(smol::block_on(async {
                 async_main::main().await
-            })
+            }))
         }
     } else if #[cfg(feature = "net-std")] {
-        fn main() -> Result<()> {
-            std_main::main()
+        fn main() -> ExitCode {
+            status::report(std_main::main())
         }
     } else {
         compile_error!("One of the net features must be enabled!!!");
@@ -0,0 +1,78 @@
+use crate::args::parse_server_addr;
+use anyhow::{Result, bail};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    net::SocketAddr,
+};
+
+/// Public resolvers queried by `--propagation` when no resolvers file is given.
+const DEFAULT_RESOLVERS: [(&str, &str); 12] = [
+    ("8.8.8.8", "Google"),
+    ("8.8.4.4", "Google"),
+    ("1.1.1.1", "Cloudflare"),
+    ("1.0.0.1", "Cloudflare"),
+    ("9.9.9.9", "Quad9"),
+    ("149.112.112.112", "Quad9"),
+    ("208.67.222.222", "OpenDNS"),
+    ("208.67.220.220", "OpenDNS"),
+    ("4.2.2.1", "Level3"),
+    ("4.2.2.2", "Level3"),
+    ("8.26.56.26", "Comodo"),
+    ("8.20.247.20", "Comodo"),
+];
+
+#[derive(Debug, Clone)]
+pub struct Resolver {
+    pub addr: SocketAddr,
+    pub name: String,
+}
+
+/// Returns the built-in list of public resolvers, listening on `port`.
+pub fn default_resolvers(port: u16) -> Vec<Resolver> {
+    DEFAULT_RESOLVERS
+        .iter()
+        .map(|(addr, name)| Resolver {
+            addr: parse_server_addr(addr, port).expect("bad built-in resolver address"),
+            name: name.to_string(),
+        })
+        .collect()
+}
+
+/// Loads a list of resolvers from a file.
+///
+/// Every non-empty line specifies a resolver address, optionally followed by a
+/// port (`1.1.1.1#53`, `[::1]:5353`) and a free-form name. Lines starting with
+/// `#` or `;` are comments.
+pub fn load_resolvers(path: &str, default_port: u16) -> Result<Vec<Resolver>> {
+    let mut resolvers = Vec::new();
+
+    let f = File::open(path)?;
+    for (index, line) in BufReader::new(f).lines().enumerate() {
+        let line = line?;
+        let trimmed = line.trim();
+
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with(';') {
+            continue;
+        }
+
+        let (addr, name) = match trimmed.split_once(char::is_whitespace) {
+            Some((addr, name)) => (addr, name.trim()),
+            None => (trimmed, ""),
+        };
+
+        match parse_server_addr(addr, default_port) {
+            Some(addr) => resolvers.push(Resolver {
+                addr,
+                name: name.to_string(),
+            }),
+            None => bail!("{path}:{}: bad resolver address: {addr}", index + 1),
+        }
+    }
+
+    if resolvers.is_empty() {
+        bail!("{path}: no resolvers");
+    }
+
+    Ok(resolvers)
+}
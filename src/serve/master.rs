@@ -0,0 +1,775 @@
+use crate::serve::name::{Name, unescape};
+use anyhow::{Context, Result, bail};
+use base64::{Engine as _, engine::general_purpose::STANDARD as Base64Engine};
+use chrono::NaiveDateTime;
+use rsdns::records::{Class, Type};
+use std::{
+    collections::BTreeMap,
+    net::{Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+// the record types rsdns has no constants for, encoded from their presentation format
+const DNAME: u16 = 39;
+const DS: u16 = 43;
+const SSHFP: u16 = 44;
+const RRSIG: u16 = 46;
+const NSEC: u16 = 47;
+const DNSKEY: u16 = 48;
+const TLSA: u16 = 52;
+const CDS: u16 = 59;
+const CDNSKEY: u16 = 60;
+const SVCB: u16 = 64;
+const HTTPS: u16 = 65;
+const SPF: u16 = 99;
+const CAA: u16 = 257;
+
+/// The names of the record types rsdns has no constants for, from the IANA registry.
+const TYPE_NAMES: [(&str, u16); 64] = [
+    ("RP", 17),
+    ("AFSDB", 18),
+    ("X25", 19),
+    ("ISDN", 20),
+    ("RT", 21),
+    ("NSAP", 22),
+    ("NSAP-PTR", 23),
+    ("SIG", 24),
+    ("KEY", 25),
+    ("PX", 26),
+    ("GPOS", 27),
+    ("LOC", 29),
+    ("NXT", 30),
+    ("EID", 31),
+    ("NIMLOC", 32),
+    ("ATMA", 34),
+    ("NAPTR", 35),
+    ("KX", 36),
+    ("CERT", 37),
+    ("A6", 38),
+    ("DNAME", DNAME),
+    ("SINK", 40),
+    ("APL", 42),
+    ("DS", DS),
+    ("SSHFP", SSHFP),
+    ("IPSECKEY", 45),
+    ("RRSIG", RRSIG),
+    ("NSEC", NSEC),
+    ("DNSKEY", DNSKEY),
+    ("DHCID", 49),
+    ("NSEC3", 50),
+    ("NSEC3PARAM", 51),
+    ("TLSA", TLSA),
+    ("SMIMEA", 53),
+    ("HIP", 55),
+    ("NINFO", 56),
+    ("RKEY", 57),
+    ("TALINK", 58),
+    ("CDS", CDS),
+    ("CDNSKEY", CDNSKEY),
+    ("OPENPGPKEY", 61),
+    ("CSYNC", 62),
+    ("ZONEMD", 63),
+    ("SVCB", SVCB),
+    ("HTTPS", HTTPS),
+    ("DSYNC", 66),
+    ("SPF", SPF),
+    ("NID", 104),
+    ("L32", 105),
+    ("L64", 106),
+    ("LP", 107),
+    ("EUI48", 108),
+    ("EUI64", 109),
+    ("TKEY", 249),
+    ("TSIG", 250),
+    ("IXFR", 251),
+    ("URI", 256),
+    ("CAA", CAA),
+    ("AVC", 258),
+    ("DOA", 259),
+    ("AMTRELAY", 260),
+    ("RESINFO", 261),
+    ("TA", 32768),
+    ("DLV", 32769),
+];
+
+/// The keys of SVCB and HTTPS parameters, RFC 9460.
+const SVC_PARAM_KEYS: [&str; 7] = [
+    "mandatory",
+    "alpn",
+    "no-default-alpn",
+    "port",
+    "ipv4hint",
+    "ech",
+    "ipv6hint",
+];
+
+/// A resource record read from a master file.
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub name: Name,
+    pub rtype: Type,
+    pub rclass: Class,
+    pub ttl: u32,
+    pub rdata: Vec<u8>,
+    /// The domain name in the record data: the CNAME target, or a host whose
+    /// addresses are returned in the additional section (NS, MX, SRV).
+    pub target: Option<Name>,
+}
+
+#[derive(Debug)]
+struct Token {
+    text: String,
+    quoted: bool,
+}
+
+/// A logical line of a master file, with parentheses already joined.
+#[derive(Debug)]
+struct Entry {
+    line: usize,
+    /// The entry starts with a blank, i.e. it has no owner name.
+    blank: bool,
+    tokens: Vec<Token>,
+}
+
+/// Parses the contents of an RFC 1035 master file.
+///
+/// `$ORIGIN` and `$TTL` directives are supported; `$INCLUDE` is not.
+/// Record data of types ch4 can't encode may be given in the RFC 3597 generic format.
+pub fn parse(text: &str, origin: Option<Name>) -> Result<Vec<Record>> {
+    let mut records = Vec::new();
+    let mut origin = origin;
+    let mut default_ttl: Option<u32> = None;
+    let mut last_ttl: Option<u32> = None;
+    let mut last_owner: Option<Name> = None;
+    let mut last_class = Class::IN;
+
+    for entry in entries(text)? {
+        let line = entry.line;
+        let tokens = &entry.tokens[..];
+        if tokens.is_empty() {
+            continue;
+        }
+
+        if !entry.blank && tokens[0].text.starts_with('$') {
+            let Some(arg) = tokens.get(1) else {
+                bail!("line {line}: missing argument of {}", tokens[0].text);
+            };
+            match tokens[0].text.to_uppercase().as_str() {
+                "$ORIGIN" => {
+                    origin = Some(
+                        Name::parse(&arg.text, origin.as_ref())
+                            .with_context(|| format!("line {line}"))?,
+                    );
+                }
+                "$TTL" => {
+                    default_ttl =
+                        Some(parse_ttl(&arg.text).with_context(|| format!("line {line}"))?)
+                }
+                d => bail!("line {line}: unsupported directive {d}"),
+            }
+            continue;
+        }
+
+        let mut rest = tokens;
+        let owner = if entry.blank {
+            match last_owner {
+                Some(ref owner) => owner.clone(),
+                None => bail!("line {line}: no owner name"),
+            }
+        } else {
+            rest = &rest[1..];
+            Name::parse(&tokens[0].text, origin.as_ref()).with_context(|| format!("line {line}"))?
+        };
+
+        let mut ttl = None;
+        let mut class = None;
+        while let Some(token) = rest.first() {
+            if ttl.is_none() && token.text.starts_with(|c: char| c.is_ascii_digit()) {
+                ttl = Some(parse_ttl(&token.text).with_context(|| format!("line {line}"))?);
+            } else if class.is_none()
+                && let Ok(c) = Class::from_str(&token.text.to_uppercase())
+            {
+                class = Some(c);
+            } else {
+                break;
+            }
+            rest = &rest[1..];
+        }
+
+        let Some(token) = rest.first() else {
+            bail!("line {line}: missing record type");
+        };
+        let Some(rtype) = parse_type(&token.text) else {
+            bail!("line {line}: unknown record type {}", token.text);
+        };
+
+        let (rdata, target) = encode_rdata(rtype, &rest[1..], origin.as_ref())
+            .with_context(|| format!("line {line}: bad {rtype} record data"))?;
+
+        let ttl = match ttl.or(default_ttl).or(last_ttl) {
+            Some(ttl) => ttl,
+            // RFC 1035: the SOA minimum is the default TTL of the zone
+            None if rtype == Type::SOA => soa_minimum(&rdata),
+            None => bail!("line {line}: no TTL"),
+        };
+        let rclass = class.unwrap_or(last_class);
+
+        last_owner = Some(owner.clone());
+        last_ttl = Some(ttl);
+        last_class = rclass;
+
+        records.push(Record {
+            name: owner,
+            rtype,
+            rclass,
+            ttl,
+            rdata,
+            target,
+        });
+    }
+
+    Ok(records)
+}
+
+/// Splits the text into logical lines of tokens.
+fn entries(text: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut line = 1;
+    let mut depth = 0;
+    let mut entry: Option<Entry> = None;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let current = entry.get_or_insert_with(|| Entry {
+            line,
+            blank: c == ' ' || c == '\t',
+            tokens: Vec::new(),
+        });
+        match c {
+            '\n' => {
+                line += 1;
+                if depth == 0 {
+                    entries.extend(entry.take());
+                }
+            }
+            ' ' | '\t' | '\r' => {}
+            ';' => while chars.next_if(|&c| c != '\n').is_some() {},
+            '(' => depth += 1,
+            ')' => {
+                if depth == 0 {
+                    bail!("line {line}: unbalanced parentheses");
+                }
+                depth -= 1;
+            }
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => {
+                            text.push('\\');
+                            text.extend(chars.next());
+                        }
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            text.push(c);
+                        }
+                        None => bail!("line {line}: unterminated quoted string"),
+                    }
+                }
+                current.tokens.push(Token { text, quoted: true });
+            }
+            c => {
+                let mut text = String::from(c);
+                if c == '\\' {
+                    text.extend(chars.next());
+                }
+                while let Some(c) = chars
+                    .next_if(|&c| !matches!(c, ' ' | '\t' | '\r' | '\n' | ';' | '(' | ')' | '"'))
+                {
+                    text.push(c);
+                    if c == '\\' {
+                        text.extend(chars.next());
+                    }
+                }
+                current.tokens.push(Token {
+                    text,
+                    quoted: false,
+                });
+            }
+        }
+    }
+
+    if depth != 0 {
+        bail!("line {line}: unbalanced parentheses");
+    }
+    entries.extend(entry);
+    Ok(entries)
+}
+
+/// Parses a TTL, either in seconds or with BIND-style units (`1h30m`).
+pub fn parse_ttl(s: &str) -> Result<u32> {
+    if let Ok(v) = u32::from_str(s) {
+        return Ok(v);
+    }
+
+    let mut total: u64 = 0;
+    let mut value: Option<u64> = None;
+    for c in s.chars() {
+        if let Some(d) = c.to_digit(10) {
+            value = Some(value.unwrap_or(0) * 10 + d as u64);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 604800,
+            _ => bail!("bad TTL: {s}"),
+        };
+        match value.take() {
+            Some(v) => total += v * unit,
+            None => bail!("bad TTL: {s}"),
+        }
+        if total > u32::MAX as u64 {
+            bail!("TTL is too large: {s}");
+        }
+    }
+    if value.is_some() {
+        bail!("bad TTL: {s}");
+    }
+    Ok(total as u32)
+}
+
+fn soa_minimum(rdata: &[u8]) -> u32 {
+    let min = &rdata[rdata.len() - 4..];
+    u32::from_be_bytes([min[0], min[1], min[2], min[3]])
+}
+
+/// Parses a record type by its name, as known to rsdns or listed in [`TYPE_NAMES`],
+/// or in the RFC 3597 `TYPEnnn` form.
+pub fn parse_type(s: &str) -> Option<Type> {
+    let s = s.to_uppercase();
+    if let Ok(rtype) = Type::from_str(&s) {
+        return Some(rtype);
+    }
+    TYPE_NAMES
+        .iter()
+        .find(|(name, _)| *name == s)
+        .map(|&(_, value)| Type::from(value))
+}
+
+/// Resolves the escape sequences of a token.
+fn unescaped(text: &str) -> Result<Vec<u8>> {
+    let bytes = text.as_bytes();
+    let mut s = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' {
+            let (b, len) = unescape(&bytes[i..])?;
+            s.push(b);
+            i += len;
+        } else {
+            s.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Ok(s)
+}
+
+fn char_string(token: &Token, buf: &mut Vec<u8>) -> Result<()> {
+    let s = unescaped(&token.text)?;
+    if s.len() > 255 {
+        bail!("character string is too long");
+    }
+    buf.push(s.len() as u8);
+    buf.extend_from_slice(&s);
+    Ok(())
+}
+
+fn number<T: FromStr>(token: &Token) -> Result<T> {
+    match T::from_str(&token.text) {
+        Ok(v) => Ok(v),
+        Err(_) => bail!("bad number: {}", token.text),
+    }
+}
+
+/// Encodes record data given in presentation format.
+fn encode_rdata(
+    rtype: Type,
+    tokens: &[Token],
+    origin: Option<&Name>,
+) -> Result<(Vec<u8>, Option<Name>)> {
+    let mut rdata = Vec::new();
+    let name = |token: &Token| Name::parse(&token.text, origin);
+
+    if let Some(first) = tokens.first()
+        && first.text == "\\#"
+        && !first.quoted
+    {
+        let Some(len) = tokens.get(1) else {
+            bail!("missing generic data length");
+        };
+        let len: usize = number(len)?;
+        let hex: String = tokens[2..].iter().map(|t| t.text.as_str()).collect();
+        rdata = decode_hex(&hex)?;
+        if rdata.len() != len {
+            bail!("generic data length mismatch");
+        }
+        if rtype == Type::SOA {
+            check_soa(&rdata)?;
+        }
+        return Ok((rdata, None));
+    }
+
+    let expected = match rtype {
+        Type::A | Type::AAAA | Type::NS | Type::CNAME | Type::PTR => Some(1),
+        Type::MX | Type::HINFO => Some(2),
+        Type::SRV => Some(4),
+        Type::SOA => Some(7),
+        Type::TXT => None,
+        _ => return Ok((encode_other_rdata(rtype, tokens, origin)?, None)),
+    };
+    if expected.is_some_and(|n| n != tokens.len()) || tokens.is_empty() {
+        bail!("wrong number of fields");
+    }
+
+    let mut target = None;
+    match rtype {
+        Type::A => match Ipv4Addr::from_str(&tokens[0].text) {
+            Ok(addr) => rdata.extend_from_slice(&addr.octets()),
+            Err(_) => bail!("bad IPv4 address: {}", tokens[0].text),
+        },
+        Type::AAAA => match Ipv6Addr::from_str(&tokens[0].text) {
+            Ok(addr) => rdata.extend_from_slice(&addr.octets()),
+            Err(_) => bail!("bad IPv6 address: {}", tokens[0].text),
+        },
+        Type::NS | Type::CNAME | Type::PTR => {
+            let n = name(&tokens[0])?;
+            n.to_wire(&mut rdata);
+            if rtype != Type::PTR {
+                target = Some(n);
+            }
+        }
+        Type::MX => {
+            rdata.extend_from_slice(&number::<u16>(&tokens[0])?.to_be_bytes());
+            let n = name(&tokens[1])?;
+            n.to_wire(&mut rdata);
+            target = Some(n);
+        }
+        Type::SRV => {
+            for t in &tokens[..3] {
+                rdata.extend_from_slice(&number::<u16>(t)?.to_be_bytes());
+            }
+            let n = name(&tokens[3])?;
+            n.to_wire(&mut rdata);
+            target = Some(n);
+        }
+        Type::SOA => {
+            name(&tokens[0])?.to_wire(&mut rdata);
+            name(&tokens[1])?.to_wire(&mut rdata);
+            rdata.extend_from_slice(&number::<u32>(&tokens[2])?.to_be_bytes());
+            for t in &tokens[3..] {
+                rdata.extend_from_slice(&parse_ttl(&t.text)?.to_be_bytes());
+            }
+        }
+        Type::TXT | Type::HINFO => {
+            for t in tokens {
+                char_string(t, &mut rdata)?;
+            }
+        }
+        _ => unreachable!(),
+    }
+
+    if rdata.len() > u16::MAX as usize {
+        bail!("record data is too long");
+    }
+    Ok((rdata, target))
+}
+
+/// Encodes the record data of the types rsdns has no constants for, in the
+/// presentation format dig prints them in.
+fn encode_other_rdata(rtype: Type, tokens: &[Token], origin: Option<&Name>) -> Result<Vec<u8>> {
+    // the number of fields, and whether the last one may be split into several tokens
+    let (fields, split) = match rtype.value() {
+        DNAME => (1, false),
+        SPF => (1, true),
+        CAA => (3, false),
+        SSHFP => (3, true),
+        DS | CDS | DNSKEY | CDNSKEY | TLSA => (4, true),
+        RRSIG => (9, true),
+        NSEC => (1, true),
+        SVCB | HTTPS => (2, true),
+        _ => bail!("unsupported record type, use the generic \\# format"),
+    };
+    if tokens.len() < fields || (!split && tokens.len() > fields) {
+        bail!("wrong number of fields");
+    }
+    let joined = |tokens: &[Token]| tokens.iter().map(|t| t.text.as_str()).collect::<String>();
+
+    let mut rdata = Vec::new();
+    match rtype.value() {
+        DNAME => Name::parse(&tokens[0].text, origin)?.to_wire(&mut rdata),
+        SPF => {
+            for t in tokens {
+                char_string(t, &mut rdata)?;
+            }
+        }
+        CAA => {
+            rdata.push(number::<u8>(&tokens[0])?);
+            let tag = &tokens[1].text;
+            if tag.is_empty() || tag.len() > 255 || !tag.bytes().all(|b| b.is_ascii_alphanumeric())
+            {
+                bail!("bad CAA tag: {tag}");
+            }
+            rdata.push(tag.len() as u8);
+            rdata.extend_from_slice(tag.as_bytes());
+            rdata.extend_from_slice(&unescaped(&tokens[2].text)?);
+        }
+        SSHFP => {
+            rdata.push(number::<u8>(&tokens[0])?);
+            rdata.push(number::<u8>(&tokens[1])?);
+            rdata.extend_from_slice(&decode_hex(&joined(&tokens[2..]))?);
+        }
+        DS | CDS => {
+            rdata.extend_from_slice(&number::<u16>(&tokens[0])?.to_be_bytes());
+            rdata.push(number::<u8>(&tokens[1])?);
+            rdata.push(number::<u8>(&tokens[2])?);
+            rdata.extend_from_slice(&decode_hex(&joined(&tokens[3..]))?);
+        }
+        TLSA => {
+            for t in &tokens[..3] {
+                rdata.push(number::<u8>(t)?);
+            }
+            rdata.extend_from_slice(&decode_hex(&joined(&tokens[3..]))?);
+        }
+        DNSKEY | CDNSKEY => {
+            rdata.extend_from_slice(&number::<u16>(&tokens[0])?.to_be_bytes());
+            rdata.push(number::<u8>(&tokens[1])?);
+            rdata.push(number::<u8>(&tokens[2])?);
+            rdata.extend_from_slice(&decode_base64(&joined(&tokens[3..]))?);
+        }
+        RRSIG => {
+            let Some(covered) = parse_type(&tokens[0].text) else {
+                bail!("unknown record type {}", tokens[0].text);
+            };
+            rdata.extend_from_slice(&covered.value().to_be_bytes());
+            rdata.push(number::<u8>(&tokens[1])?);
+            rdata.push(number::<u8>(&tokens[2])?);
+            rdata.extend_from_slice(&parse_ttl(&tokens[3].text)?.to_be_bytes());
+            for t in &tokens[4..6] {
+                rdata.extend_from_slice(&signature_time(&t.text)?.to_be_bytes());
+            }
+            rdata.extend_from_slice(&number::<u16>(&tokens[6])?.to_be_bytes());
+            Name::parse(&tokens[7].text, origin)?.to_wire(&mut rdata);
+            rdata.extend_from_slice(&decode_base64(&joined(&tokens[8..]))?);
+        }
+        NSEC => {
+            Name::parse(&tokens[0].text, origin)?.to_wire(&mut rdata);
+            type_bitmaps(&tokens[1..], &mut rdata)?;
+        }
+        SVCB | HTTPS => {
+            rdata.extend_from_slice(&number::<u16>(&tokens[0])?.to_be_bytes());
+            Name::parse(&tokens[1].text, origin)?.to_wire(&mut rdata);
+            svc_params(&tokens[2..], &mut rdata)?;
+        }
+        _ => unreachable!(),
+    }
+
+    if rdata.len() > u16::MAX as usize {
+        bail!("record data is too long");
+    }
+    Ok(rdata)
+}
+
+fn decode_base64(s: &str) -> Result<Vec<u8>> {
+    match Base64Engine.decode(s) {
+        Ok(bytes) => Ok(bytes),
+        Err(_) => bail!("bad base64 data: {s}"),
+    }
+}
+
+/// Parses an RRSIG expiration or inception time, as `YYYYMMDDHHmmSS` in UTC or in
+/// seconds since the epoch.
+fn signature_time(s: &str) -> Result<u32> {
+    if s.len() == 14
+        && let Ok(t) = NaiveDateTime::parse_from_str(s, "%Y%m%d%H%M%S")
+    {
+        // RFC 4034: the time is a serial number, wrapping around in 2106
+        return Ok(t.and_utc().timestamp() as u32);
+    }
+    match u32::from_str(s) {
+        Ok(secs) => Ok(secs),
+        Err(_) => bail!("bad signature time: {s}"),
+    }
+}
+
+/// Encodes the type bitmaps of an NSEC record, RFC 4034 section 4.1.2.
+fn type_bitmaps(tokens: &[Token], rdata: &mut Vec<u8>) -> Result<()> {
+    let mut windows: BTreeMap<u8, [u8; 32]> = BTreeMap::new();
+    for t in tokens {
+        let Some(rtype) = parse_type(&t.text) else {
+            bail!("unknown record type {}", t.text);
+        };
+        let [window, low] = rtype.value().to_be_bytes();
+        windows.entry(window).or_insert([0; 32])[low as usize / 8] |= 0x80 >> (low % 8);
+    }
+    for (window, bitmap) in windows {
+        // a window has at least one bit set
+        let len = bitmap.iter().rposition(|&b| b != 0).unwrap() + 1;
+        rdata.push(window);
+        rdata.push(len as u8);
+        rdata.extend_from_slice(&bitmap[..len]);
+    }
+    Ok(())
+}
+
+/// Encodes the `key=value` parameters of an SVCB or HTTPS record, RFC 9460.
+fn svc_params(tokens: &[Token], rdata: &mut Vec<u8>) -> Result<()> {
+    let mut params = BTreeMap::new();
+    let mut tokens = tokens.iter();
+    while let Some(token) = tokens.next() {
+        let (key, value) = match token.text.split_once('=') {
+            // a quoted value is a token of its own: alpn="h3,h2"
+            Some((key, "")) => match tokens.next() {
+                Some(value) if value.quoted => (key, value.text.as_str()),
+                _ => bail!("missing value of {key}"),
+            },
+            Some((key, value)) => (key, value),
+            None => (token.text.as_str(), ""),
+        };
+        let key_value = svc_param_key(key)?;
+
+        let mut data = Vec::new();
+        match key_value {
+            0 => {
+                for k in value.split(',') {
+                    data.extend_from_slice(&svc_param_key(k)?.to_be_bytes());
+                }
+            }
+            1 => {
+                for id in comma_separated(value)? {
+                    if id.is_empty() || id.len() > 255 {
+                        bail!("bad alpn value: {value}");
+                    }
+                    data.push(id.len() as u8);
+                    data.extend_from_slice(&id);
+                }
+            }
+            2 if value.is_empty() => {}
+            2 => bail!("no-default-alpn takes no value"),
+            3 => match u16::from_str(value) {
+                Ok(port) => data.extend_from_slice(&port.to_be_bytes()),
+                Err(_) => bail!("bad port: {value}"),
+            },
+            4 => {
+                for addr in value.split(',') {
+                    match Ipv4Addr::from_str(addr) {
+                        Ok(addr) => data.extend_from_slice(&addr.octets()),
+                        Err(_) => bail!("bad IPv4 address: {addr}"),
+                    }
+                }
+            }
+            5 => data = decode_base64(value)?,
+            6 => {
+                for addr in value.split(',') {
+                    match Ipv6Addr::from_str(addr) {
+                        Ok(addr) => data.extend_from_slice(&addr.octets()),
+                        Err(_) => bail!("bad IPv6 address: {addr}"),
+                    }
+                }
+            }
+            _ => data = unescaped(value)?,
+        }
+        if params.insert(key_value, data).is_some() {
+            bail!("duplicate parameter {key}");
+        }
+    }
+
+    // parameters go in increasing key order
+    for (key, data) in params {
+        rdata.extend_from_slice(&key.to_be_bytes());
+        rdata.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        rdata.extend_from_slice(&data);
+    }
+    Ok(())
+}
+
+/// Parses the key of an SVCB parameter, by its name or in the `keyNNNNN` form.
+fn svc_param_key(s: &str) -> Result<u16> {
+    if let Some(key) = SVC_PARAM_KEYS.iter().position(|k| *k == s) {
+        return Ok(key as u16);
+    }
+    match s.strip_prefix("key").map(u16::from_str) {
+        Some(Ok(key)) => Ok(key),
+        _ => bail!("unknown parameter {s}"),
+    }
+}
+
+/// Splits a value on the commas which aren't escaped, resolving the escape sequences.
+fn comma_separated(s: &str) -> Result<Vec<Vec<u8>>> {
+    let bytes = s.as_bytes();
+    let mut items = vec![Vec::new()];
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => {
+                let (b, len) = unescape(&bytes[i..])?;
+                items.last_mut().unwrap().push(b);
+                i += len;
+            }
+            b',' => {
+                items.push(Vec::new());
+                i += 1;
+            }
+            b => {
+                items.last_mut().unwrap().push(b);
+                i += 1;
+            }
+        }
+    }
+    Ok(items)
+}
+
+/// Checks that SOA data given in the generic format holds two uncompressed names
+/// followed by the five 32-bit fields.
+fn check_soa(rdata: &[u8]) -> Result<()> {
+    let mut pos = 0;
+    for _ in 0..2 {
+        loop {
+            let Some(&len) = rdata.get(pos) else {
+                bail!("SOA name overflows the record data");
+            };
+            if len & 0xC0 != 0 {
+                bail!("SOA names must be uncompressed");
+            }
+            pos += 1 + len as usize;
+            if len == 0 {
+                break;
+            }
+        }
+    }
+    if rdata.len() != pos + 20 {
+        bail!("SOA data must end with 20 bytes of fields");
+    }
+    Ok(())
+}
+
+/// Decodes a string of hex digits, two per byte.
+pub fn decode_hex(s: &str) -> Result<Vec<u8>> {
+    let bytes = s.as_bytes();
+    if !bytes.len().is_multiple_of(2) {
+        bail!("odd number of hex digits");
+    }
+    let digit = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
+    bytes
+        .chunks(2)
+        .map(|pair| match (digit(pair[0]), digit(pair[1])) {
+            (Some(high), Some(low)) => Ok(high << 4 | low),
+            _ => bail!("bad hex data: {s}"),
+        })
+        .collect()
+}
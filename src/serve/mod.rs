@@ -0,0 +1,229 @@
+pub mod master;
+pub mod name;
+mod replay;
+pub mod wire;
+mod zone;
+
+//
+// ----------------------------------------------------------------------
+//
+
+use crate::{args::ServeArgs, status::Status};
+use anyhow::{Result, bail};
+use rsdns::{
+    message::{Header, OpCode, Question, RCode, reader::MessageReader},
+    names::InlineName,
+    records::Type,
+};
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, UdpSocket},
+    sync::Arc,
+    thread,
+};
+
+/// The UDP payload size limit of clients not using EDNS.
+const UDP_MIN_PAYLOAD_SIZE: usize = 512;
+
+/// A parsed incoming query.
+pub struct Query<'a> {
+    pub msg: &'a [u8],
+    pub header: Header,
+    pub question: Question,
+    /// The offset of the first byte past the question section.
+    pub question_end: usize,
+    /// The max UDP payload size advertised by the client.
+    pub udp_size: usize,
+    pub transport: Transport,
+}
+
+impl<'a> Query<'a> {
+    fn parse(msg: &'a [u8], transport: Transport) -> Result<Query<'a>> {
+        let mut mr = MessageReader::new(msg)?;
+        let header = mr.header()?;
+        let question = mr.the_question()?;
+        let Some(question_end) = wire::question_end(msg) else {
+            bail!("malformed question");
+        };
+
+        let mut udp_size = UDP_MIN_PAYLOAD_SIZE;
+        while mr.has_records() {
+            let rec_header = mr.record_header::<InlineName>()?;
+            let marker = rec_header.marker();
+            if rec_header.rtype() == Type::OPT {
+                let opt = mr.opt_record(marker)?;
+                udp_size = (opt.udp_payload_size() as usize).max(UDP_MIN_PAYLOAD_SIZE);
+            } else {
+                mr.skip_record_data(marker)?;
+            }
+        }
+
+        Ok(Query {
+            msg,
+            header,
+            question,
+            question_end,
+            udp_size,
+            transport,
+        })
+    }
+}
+
+/// A source of responses to incoming queries.
+pub trait Responder: Send + Sync {
+    /// Builds the response messages; only zone transfers consist of more than one.
+    fn respond(&self, query: &Query) -> Vec<Vec<u8>>;
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+impl std::fmt::Display for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Transport::Udp => "udp",
+            Transport::Tcp => "tcp",
+        })
+    }
+}
+
+/// Runs the server until it fails.
+pub fn run(args: &ServeArgs) -> Result<Status> {
+    let responder: Arc<dyn Responder> = match (&args.read_path, &args.zone_path) {
+        (Some(path), _) => {
+            let replay = replay::Replay::load(path, args.unmatched)?;
+            eprintln!(
+                ";; serving {} responses from {path} on {}",
+                replay.len(),
+                args.listen
+            );
+            Arc::new(replay)
+        }
+        (None, Some(path)) => {
+            let zone = zone::Zone::load(path, args.origin.as_deref())?;
+            eprintln!(
+                ";; serving zone {} with {} records from {path} on {}",
+                zone.apex(),
+                zone.len(),
+                args.listen
+            );
+            Arc::new(zone)
+        }
+        (None, None) => bail!("nothing to serve"),
+    };
+
+    let udp = UdpSocket::bind(args.listen)?;
+    let tcp = TcpListener::bind(args.listen)?;
+
+    let tcp_responder = responder.clone();
+    thread::spawn(move || serve_tcp(tcp, tcp_responder));
+
+    serve_udp(udp, responder.as_ref());
+    Ok(Status::Success)
+}
+
+fn serve_udp(socket: UdpSocket, responder: &dyn Responder) {
+    let mut buf = [0u8; u16::MAX as usize];
+    loop {
+        // an error of a single datagram, e.g. an ICMP port unreachable reported as
+        // ECONNREFUSED, must not stop the server
+        let (size, peer) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) => {
+                eprintln!(";; udp receive error: {e}");
+                continue;
+            }
+        };
+        if let Some(resp) = handle(responder, &buf[..size], peer, Transport::Udp)
+            .into_iter()
+            .next()
+            && let Err(e) = socket.send_to(&resp, peer)
+        {
+            eprintln!(";; udp send error to {peer}: {e}");
+        }
+    }
+}
+
+fn serve_tcp(listener: TcpListener, responder: Arc<dyn Responder>) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let responder = responder.clone();
+                thread::spawn(move || {
+                    if let Err(e) = serve_tcp_stream(stream, responder.as_ref()) {
+                        eprintln!(";; tcp connection error: {e:#}");
+                    }
+                });
+            }
+            Err(e) => eprintln!(";; tcp accept error: {e}"),
+        }
+    }
+}
+
+fn serve_tcp_stream(mut stream: TcpStream, responder: &dyn Responder) -> Result<()> {
+    let peer = stream.peer_addr()?;
+    let mut buf = [0u8; u16::MAX as usize];
+    loop {
+        let mut len = [0u8; 2];
+        if stream.read_exact(&mut len).is_err() {
+            // the client closed the connection
+            return Ok(());
+        }
+        let len = u16::from_be_bytes(len) as usize;
+        stream.read_exact(&mut buf[..len])?;
+
+        for resp in handle(responder, &buf[..len], peer, Transport::Tcp) {
+            stream.write_all(&(resp.len() as u16).to_be_bytes())?;
+            stream.write_all(&resp)?;
+        }
+    }
+}
+
+/// Builds the responses to a single message; none if the message must be ignored.
+fn handle(
+    responder: &dyn Responder,
+    msg: &[u8],
+    peer: SocketAddr,
+    transport: Transport,
+) -> Vec<Vec<u8>> {
+    // ignore runts and responses
+    if msg.len() < wire::HEADER_LEN || msg[2] & 0x80 != 0 {
+        return Vec::new();
+    }
+
+    let query = match Query::parse(msg, transport) {
+        Ok(query) => query,
+        Err(e) => {
+            eprintln!(";; {peer} {transport}: bad query: {e}");
+            return vec![wire::error_response(msg, wire::HEADER_LEN, RCode::FORMERR)];
+        }
+    };
+
+    let mut resp = if query.header.flags.opcode() == OpCode::QUERY {
+        responder.respond(&query)
+    } else {
+        vec![wire::error_response(msg, query.question_end, RCode::NOTIMP)]
+    };
+
+    if transport == Transport::Udp
+        && let Some(first) = resp.first_mut()
+        && first.len() > query.udp_size
+    {
+        *first = wire::truncate(first);
+    }
+
+    let q = &query.question;
+    let rcode = MessageReader::new(resp.first().map(|r| r.as_slice()).unwrap_or_default())
+        .and_then(|mut mr| mr.header())
+        .map(|h| h.flags.response_code().to_string())
+        .unwrap_or_default();
+    eprintln!(
+        ";; {peer} {transport}: {} {} {}: {rcode}",
+        q.qname, q.qclass, q.qtype
+    );
+
+    resp
+}
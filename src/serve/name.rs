@@ -0,0 +1,182 @@
+use anyhow::{Result, bail};
+use std::fmt::{self, Display, Write};
+
+const MAX_LABEL_LEN: usize = 63;
+const MAX_NAME_LEN: usize = 255;
+
+/// An absolute domain name, kept as a list of labels.
+///
+/// Comparison is ASCII case-insensitive, as required by RFC 1035.
+#[derive(Debug, Clone, Default)]
+pub struct Name {
+    labels: Vec<Vec<u8>>,
+}
+
+impl Name {
+    pub fn root() -> Name {
+        Name::default()
+    }
+
+    /// Parses a domain name in presentation format.
+    ///
+    /// Names not ending with a dot are relative to `origin`. `@` denotes `origin` itself.
+    /// Escapes `\X` and `\DDD` are decoded.
+    pub fn parse(s: &str, origin: Option<&Name>) -> Result<Name> {
+        if s == "@" {
+            return match origin {
+                Some(origin) => Ok(origin.clone()),
+                None => bail!("no origin for @"),
+            };
+        }
+        if s == "." {
+            return Ok(Name::root());
+        }
+        if s.is_empty() {
+            bail!("empty domain name");
+        }
+
+        let mut labels = Vec::new();
+        let mut label = Vec::new();
+        let mut absolute = false;
+        let bytes = s.as_bytes();
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'.' => {
+                    if label.is_empty() {
+                        bail!("empty label in domain name: {s}");
+                    }
+                    labels.push(std::mem::take(&mut label));
+                    absolute = i == bytes.len() - 1;
+                    i += 1;
+                }
+                b'\\' => {
+                    let (b, len) = unescape(&bytes[i..])?;
+                    label.push(b);
+                    i += len;
+                }
+                b => {
+                    label.push(b);
+                    i += 1;
+                }
+            }
+        }
+        if !label.is_empty() {
+            labels.push(label);
+        }
+
+        if !absolute {
+            match origin {
+                Some(origin) => labels.extend(origin.labels.iter().cloned()),
+                None => bail!("relative domain name with no origin: {s}"),
+            }
+        }
+
+        let name = Name { labels };
+        if name.labels.iter().any(|l| l.len() > MAX_LABEL_LEN) {
+            bail!("domain name label is too long: {s}");
+        }
+        if name.wire_len() > MAX_NAME_LEN {
+            bail!("domain name is too long: {s}");
+        }
+        Ok(name)
+    }
+
+    pub fn wire_len(&self) -> usize {
+        self.labels.iter().map(|l| l.len() + 1).sum::<usize>() + 1
+    }
+
+    pub fn to_wire(&self, buf: &mut Vec<u8>) {
+        for label in self.labels.iter() {
+            buf.push(label.len() as u8);
+            buf.extend_from_slice(label);
+        }
+        buf.push(0);
+    }
+
+    pub fn parent(&self) -> Option<Name> {
+        if self.labels.is_empty() {
+            return None;
+        }
+        Some(Name {
+            labels: self.labels[1..].to_vec(),
+        })
+    }
+
+    /// Checks if `self` is `other` or a name below it.
+    pub fn is_subdomain_of(&self, other: &Name) -> bool {
+        self.labels.len() >= other.labels.len()
+            && self.labels[self.labels.len() - other.labels.len()..]
+                .iter()
+                .zip(other.labels.iter())
+                .all(|(l, r)| l.eq_ignore_ascii_case(r))
+    }
+
+    /// Returns the lowercase presentation format, used as a lookup key.
+    pub fn key(&self) -> String {
+        self.to_string().to_ascii_lowercase()
+    }
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Self) -> bool {
+        self.labels.len() == other.labels.len() && self.is_subdomain_of(other)
+    }
+}
+
+impl Eq for Name {}
+
+impl Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.labels.is_empty() {
+            return f.write_char('.');
+        }
+        for label in self.labels.iter() {
+            f.write_str(&escape(label))?;
+            f.write_char('.')?;
+        }
+        Ok(())
+    }
+}
+
+/// Formats a domain name label in presentation format, escaping the characters special
+/// in master files and the non-printable bytes.
+///
+/// Only labels ch4 reads itself need this. rsdns accepts nothing but letters, digits,
+/// `-` and `_` in the names it parses, and fails the whole message otherwise, so the
+/// names it displays never hold a character to escape.
+pub fn escape(label: &[u8]) -> String {
+    let mut s = String::with_capacity(label.len());
+    for &b in label {
+        match b {
+            b'.' | b'\\' | b'"' | b';' | b'(' | b')' | b'@' | b'$' => {
+                s.push('\\');
+                s.push(b as char);
+            }
+            0x21..=0x7E => s.push(b as char),
+            _ => {
+                let _ = write!(s, "\\{b:03}");
+            }
+        }
+    }
+    s
+}
+
+/// Decodes an escape sequence starting with a backslash.
+///
+/// Returns the decoded byte and the length of the sequence.
+pub fn unescape(s: &[u8]) -> Result<(u8, usize)> {
+    match s.get(1..4) {
+        Some(d) if d.iter().all(u8::is_ascii_digit) => {
+            let v = d.iter().fold(0u32, |v, d| v * 10 + (d - b'0') as u32);
+            if v > 255 {
+                bail!("bad escape sequence: \\{}", String::from_utf8_lossy(d));
+            }
+            Ok((v as u8, 4))
+        }
+        _ => match s.get(1) {
+            Some(&b) => Ok((b, 2)),
+            None => bail!("dangling escape character"),
+        },
+    }
+}
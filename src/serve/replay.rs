@@ -0,0 +1,87 @@
+use crate::{
+    args::Unmatched,
+    fmt::save::EncodedMessage,
+    serve::{Query, Responder, wire},
+};
+use anyhow::{Result, bail};
+use rsdns::{
+    message::{RCode, reader::MessageReader},
+    names::InlineName,
+    records::{Class, Type},
+};
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+type Key = (InlineName, Type, Class);
+
+#[derive(Debug, Default)]
+struct Entry {
+    responses: Vec<Vec<u8>>,
+    next: AtomicUsize,
+}
+
+/// Answers queries with responses saved by `--save`.
+///
+/// Questions saved more than once are answered with their responses in turn.
+#[derive(Debug)]
+pub struct Replay {
+    entries: HashMap<Key, Entry>,
+    unmatched: RCode,
+}
+
+impl Replay {
+    pub fn load(path: &str, unmatched: Unmatched) -> Result<Replay> {
+        let mut entries: HashMap<Key, Entry> = HashMap::new();
+
+        for em in EncodedMessage::load_all(path)? {
+            let msg = em.msg();
+            let mut mr = MessageReader::new(&msg)?;
+            mr.header()?;
+            let q = mr.the_question()?;
+            entries
+                .entry((q.qname, q.qtype, q.qclass))
+                .or_default()
+                .responses
+                .push(msg);
+        }
+
+        if entries.is_empty() {
+            bail!("{path}: no responses");
+        }
+
+        Ok(Replay {
+            entries,
+            unmatched: match unmatched {
+                Unmatched::Refused => RCode::REFUSED,
+                Unmatched::Nxdomain => RCode::NXDOMAIN,
+            },
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.values().map(|e| e.responses.len()).sum()
+    }
+}
+
+impl Responder for Replay {
+    fn respond(&self, query: &Query) -> Vec<Vec<u8>> {
+        let q = &query.question;
+        let key = (q.qname.clone(), q.qtype, q.qclass);
+
+        match self.entries.get(&key) {
+            Some(entry) => {
+                let next = entry.next.fetch_add(1, Ordering::Relaxed);
+                let mut resp = entry.responses[next % entry.responses.len()].clone();
+                wire::set_id(&mut resp, query.header.id);
+                vec![resp]
+            }
+            None => vec![wire::error_response(
+                query.msg,
+                query.question_end,
+                self.unmatched,
+            )],
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use crate::serve::name::Name;
+use anyhow::Result;
+use rsdns::{
+    clients::{ClientConfig, EDns, Recursion},
+    message::{RCode, RecordsSection},
+    records::{Class, Type},
+};
+
+pub const HEADER_LEN: usize = 12;
+
+pub const QR: u16 = 0x8000;
+const OPCODE_MASK: u16 = 0x7800;
+const AA: u16 = 0x0400;
+const TC: u16 = 0x0200;
+pub const RD: u16 = 0x0100;
+
+/// The header flags and their names, in the order dig prints them.
+pub const FLAGS: [(u16, &str); 7] = [
+    (QR, "qr"),
+    (AA, "aa"),
+    (TC, "tc"),
+    (RD, "rd"),
+    (0x0080, "ra"),
+    (0x0020, "ad"),
+    (0x0010, "cd"),
+];
+
+/// Returns the names of the flags set in the flags field of a header.
+pub fn flag_names(flags: u16) -> Vec<&'static str> {
+    FLAGS
+        .iter()
+        .filter(|(bit, _)| flags & bit != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+fn read_u16(msg: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([msg[offset], msg[offset + 1]])
+}
+
+fn write_u16(msg: &mut [u8], offset: usize, value: u16) {
+    msg[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+/// Returns the offset of the first byte past the question section.
+pub fn question_end(msg: &[u8]) -> Option<usize> {
+    if msg.len() < HEADER_LEN {
+        return None;
+    }
+    let mut pos = HEADER_LEN;
+    for _ in 0..read_u16(msg, 4) {
+        loop {
+            let len = *msg.get(pos)? as usize;
+            if len == 0 {
+                pos += 1;
+                break;
+            }
+            if len & 0xC0 == 0xC0 {
+                pos += 2;
+                break;
+            }
+            pos += 1 + len;
+        }
+        pos += 4;
+    }
+    (pos <= msg.len()).then_some(pos)
+}
+
+pub fn set_id(msg: &mut [u8], id: u16) {
+    write_u16(msg, 0, id);
+}
+
+/// Reconstructs the query message rsdns sends with `config`.
+pub fn client_query(config: &ClientConfig, id: u16, qname: &str, qtype: Type) -> Result<Vec<u8>> {
+    let name = Name::parse(qname, Some(&Name::root()))?;
+    let edns = match config.edns() {
+        EDns::On {
+            version,
+            udp_payload_size,
+        } => {
+            // rsdns limits the advertised size to its buffer; size 0 stands for the caller's buffer
+            let limit = match config.buffer_size() {
+                0 => usize::MAX,
+                size => size,
+            };
+            Some((version, (udp_payload_size as usize).min(limit) as u16))
+        }
+        EDns::Off => None,
+    };
+
+    let mut query = vec![0u8; HEADER_LEN];
+    write_u16(&mut query, 0, id);
+    if config.recursion() == Recursion::On {
+        write_u16(&mut query, 2, RD);
+    }
+    write_u16(&mut query, 4, 1);
+    write_u16(&mut query, 10, u16::from(edns.is_some()));
+
+    name.to_wire(&mut query);
+    query.extend_from_slice(&qtype.value().to_be_bytes());
+    query.extend_from_slice(&Class::IN.value().to_be_bytes());
+
+    if let Some((version, udp_payload_size)) = edns {
+        Name::root().to_wire(&mut query);
+        query.extend_from_slice(&Type::OPT.value().to_be_bytes());
+        query.extend_from_slice(&udp_payload_size.to_be_bytes());
+        query.extend_from_slice(&((version as u32) << 16).to_be_bytes());
+        query.extend_from_slice(&0u16.to_be_bytes());
+    }
+
+    Ok(query)
+}
+
+/// Builds a response echoing the header and question of `query`, with no records.
+///
+/// `question_end` is the value returned by [`question_end`], or [`HEADER_LEN`] to
+/// drop the question.
+pub fn error_response(query: &[u8], question_end: usize, rcode: RCode) -> Vec<u8> {
+    let mut resp = query[..question_end].to_vec();
+    let flags = read_u16(query, 2);
+    let flags = QR | flags & (OPCODE_MASK | RD) | (rcode.value() & 0x000F);
+    write_u16(&mut resp, 2, flags);
+    write_u16(&mut resp, 4, if question_end > HEADER_LEN { 1 } else { 0 });
+    for offset in [6, 8, 10] {
+        write_u16(&mut resp, offset, 0);
+    }
+    resp
+}
+
+/// Strips all records from a response and sets the TC flag.
+pub fn truncate(resp: &[u8]) -> Vec<u8> {
+    let end = question_end(resp).unwrap_or(HEADER_LEN);
+    let mut truncated = resp[..end].to_vec();
+    let flags = read_u16(resp, 2);
+    write_u16(&mut truncated, 2, flags | TC);
+    for offset in [6, 8, 10] {
+        write_u16(&mut truncated, offset, 0);
+    }
+    truncated
+}
+
+/// Builds a response to a query, echoing its header fields and question.
+pub struct ResponseWriter<'a> {
+    query: &'a [u8],
+    question_end: usize,
+    pub rcode: RCode,
+    pub authoritative: bool,
+    sections: [(Vec<u8>, u16); 3],
+}
+
+impl<'a> ResponseWriter<'a> {
+    pub fn new(query: &'a [u8], question_end: usize) -> ResponseWriter<'a> {
+        ResponseWriter {
+            query,
+            question_end,
+            rcode: RCode::NOERROR,
+            authoritative: false,
+            sections: Default::default(),
+        }
+    }
+
+    pub fn add(
+        &mut self,
+        section: RecordsSection,
+        name: &Name,
+        rtype: Type,
+        rclass: Class,
+        ttl: u32,
+        rdata: &[u8],
+    ) {
+        let (buf, count) = &mut self.sections[section as usize];
+        name.to_wire(buf);
+        buf.extend_from_slice(&rtype.value().to_be_bytes());
+        buf.extend_from_slice(&rclass.value().to_be_bytes());
+        buf.extend_from_slice(&ttl.to_be_bytes());
+        buf.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        buf.extend_from_slice(rdata);
+        *count += 1;
+    }
+
+    pub fn count(&self, section: RecordsSection) -> u16 {
+        self.sections[section as usize].1
+    }
+
+    pub fn len(&self) -> usize {
+        self.question_end + self.sections.iter().map(|(b, _)| b.len()).sum::<usize>()
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        let mut resp = error_response(self.query, self.question_end, self.rcode);
+        if self.authoritative {
+            let flags = read_u16(&resp, 2);
+            write_u16(&mut resp, 2, flags | AA);
+        }
+        for (i, (buf, count)) in self.sections.iter().enumerate() {
+            write_u16(&mut resp, 6 + 2 * i, *count);
+            resp.extend_from_slice(buf);
+        }
+        resp
+    }
+}
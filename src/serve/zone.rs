@@ -0,0 +1,264 @@
+use crate::serve::{
+    Query, Responder, Transport,
+    master::{self, Record},
+    name::Name,
+    wire::{self, ResponseWriter},
+};
+use anyhow::{Context, Result, bail};
+use rsdns::{
+    message::{RCode, RecordsSection},
+    records::{Class, Type},
+};
+use std::collections::{HashMap, HashSet};
+
+/// The max number of CNAME records followed in a single response.
+const MAX_CNAME_CHAIN: usize = 16;
+
+/// The max size of a single AXFR response message.
+const AXFR_MESSAGE_SIZE: usize = 16384;
+
+/// A minimal authoritative server of a zone loaded from a master file.
+#[derive(Debug)]
+pub struct Zone {
+    apex: Name,
+    class: Class,
+    soa: Record,
+    /// All the records, in master file order.
+    records: Vec<Record>,
+    /// Record indices by owner name.
+    nodes: HashMap<String, Vec<usize>>,
+    /// Owner names and all their ancestors up to the apex, to tell empty
+    /// non-terminals from names that don't exist.
+    names: HashSet<String>,
+}
+
+impl Zone {
+    pub fn load(path: &str, origin: Option<&str>) -> Result<Zone> {
+        let origin = match origin {
+            Some(o) => Some(Name::parse(o, Some(&Name::root()))?),
+            None => None,
+        };
+        let text = std::fs::read_to_string(path).with_context(|| path.to_string())?;
+        let records = master::parse(&text, origin.clone()).with_context(|| path.to_string())?;
+
+        let mut soa = records.iter().filter(|r| r.rtype == Type::SOA);
+        let (Some(soa), None) = (soa.next(), soa.next()) else {
+            bail!("{path}: the zone must have exactly one SOA record");
+        };
+        let apex = soa.name.clone();
+        if let Some(origin) = origin
+            && origin != apex
+        {
+            bail!("{path}: the SOA record is not at the origin {origin}");
+        }
+
+        let mut nodes: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut names = HashSet::new();
+        for (i, r) in records.iter().enumerate() {
+            if !r.name.is_subdomain_of(&apex) {
+                bail!("{path}: {} is out of zone {apex}", r.name);
+            }
+            if r.rclass != soa.rclass {
+                bail!(
+                    "{path}: {} has class {}, not {}",
+                    r.name,
+                    r.rclass,
+                    soa.rclass
+                );
+            }
+            nodes.entry(r.name.key()).or_default().push(i);
+
+            let mut name = Some(r.name.clone());
+            while let Some(n) = name
+                && n.is_subdomain_of(&apex)
+            {
+                names.insert(n.key());
+                name = n.parent();
+            }
+        }
+
+        Ok(Zone {
+            apex,
+            class: soa.rclass,
+            soa: soa.clone(),
+            records,
+            nodes,
+            names,
+        })
+    }
+
+    pub fn apex(&self) -> &Name {
+        &self.apex
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    fn node(&self, name: &Name) -> impl Iterator<Item = &Record> {
+        self.nodes
+            .get(&name.key())
+            .into_iter()
+            .flatten()
+            .map(|&i| &self.records[i])
+    }
+
+    /// Finds the NS records of the delegation point closest to the apex, if `name` is
+    /// at or below one.
+    fn find_cut(&self, name: &Name) -> Option<Vec<&Record>> {
+        let mut ancestors = Vec::new();
+        let mut n = Some(name.clone());
+        while let Some(cur) = n
+            && cur != self.apex
+        {
+            n = cur.parent();
+            ancestors.push(cur);
+        }
+        ancestors.iter().rev().find_map(|a| {
+            let ns: Vec<&Record> = self.node(a).filter(|r| r.rtype == Type::NS).collect();
+            (!ns.is_empty()).then_some(ns)
+        })
+    }
+
+    /// Adds the addresses of the hosts named in `records` to the additional section.
+    fn add_additional(&self, w: &mut ResponseWriter, records: &[&Record]) {
+        let mut seen = HashSet::new();
+        let targets = records
+            .iter()
+            .filter(|r| r.rtype != Type::CNAME)
+            .filter_map(|r| r.target.as_ref());
+        for target in targets {
+            if !seen.insert(target.key()) {
+                continue;
+            }
+            for r in self.node(target) {
+                if r.rtype == Type::A || r.rtype == Type::AAAA {
+                    add_record(w, RecordsSection::Additional, r);
+                }
+            }
+        }
+    }
+
+    /// Adds the SOA record of a negative response to the authority section.
+    fn add_negative_soa(&self, w: &mut ResponseWriter) {
+        // RFC 2308 s. 3: the TTL of the SOA is the minimum of its TTL and the MINIMUM field
+        let rdata = &self.soa.rdata;
+        let min = &rdata[rdata.len() - 4..];
+        let min = u32::from_be_bytes([min[0], min[1], min[2], min[3]]);
+        w.add(
+            RecordsSection::Authority,
+            &self.soa.name,
+            Type::SOA,
+            self.soa.rclass,
+            self.soa.ttl.min(min),
+            rdata,
+        );
+    }
+
+    fn resolve(&self, w: &mut ResponseWriter, qname: &Name, qtype: Type) {
+        w.authoritative = true;
+        let mut name = qname.clone();
+
+        for _ in 0..MAX_CNAME_CHAIN {
+            if let Some(ns) = self.find_cut(&name) {
+                // a referral is not authoritative, unless it follows an in-zone CNAME
+                w.authoritative = w.count(RecordsSection::Answer) > 0;
+                for r in ns.iter() {
+                    add_record(w, RecordsSection::Authority, r);
+                }
+                self.add_additional(w, &ns);
+                return;
+            }
+
+            let node: Vec<&Record> = self.node(&name).collect();
+            if node.is_empty() {
+                if !self.names.contains(&name.key()) {
+                    w.rcode = RCode::NXDOMAIN;
+                }
+                self.add_negative_soa(w);
+                return;
+            }
+
+            let answers: Vec<&Record> = node
+                .iter()
+                .copied()
+                .filter(|r| qtype == Type::ANY || r.rtype == qtype)
+                .collect();
+            if !answers.is_empty() {
+                for r in answers.iter() {
+                    add_record(w, RecordsSection::Answer, r);
+                }
+                self.add_additional(w, &answers);
+                return;
+            }
+
+            match node.iter().find(|r| r.rtype == Type::CNAME) {
+                Some(cname) => {
+                    add_record(w, RecordsSection::Answer, cname);
+                    match cname.target {
+                        Some(ref target) if target.is_subdomain_of(&self.apex) => {
+                            name = target.clone();
+                        }
+                        _ => return,
+                    }
+                }
+                None => {
+                    self.add_negative_soa(w);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Builds the messages of a zone transfer: the SOA, all the other records, and the SOA again.
+    fn axfr(&self, query: &Query) -> Vec<Vec<u8>> {
+        let mut messages = Vec::new();
+        let mut w = ResponseWriter::new(query.msg, query.question_end);
+        w.authoritative = true;
+
+        let others = self.records.iter().filter(|r| r.rtype != Type::SOA);
+        for r in std::iter::once(&self.soa)
+            .chain(others)
+            .chain(std::iter::once(&self.soa))
+        {
+            if w.len() > AXFR_MESSAGE_SIZE {
+                messages.push(w.finish());
+                w = ResponseWriter::new(query.msg, query.question_end);
+                w.authoritative = true;
+            }
+            add_record(&mut w, RecordsSection::Answer, r);
+        }
+        messages.push(w.finish());
+        messages
+    }
+}
+
+fn add_record(w: &mut ResponseWriter, section: RecordsSection, r: &Record) {
+    w.add(section, &r.name, r.rtype, r.rclass, r.ttl, &r.rdata);
+}
+
+impl Responder for Zone {
+    fn respond(&self, query: &Query) -> Vec<Vec<u8>> {
+        let q = &query.question;
+        let error = |rcode| vec![wire::error_response(query.msg, query.question_end, rcode)];
+
+        let Ok(qname) = Name::parse(q.qname.as_str(), None) else {
+            return error(RCode::FORMERR);
+        };
+        if (q.qclass != self.class && q.qclass != Class::ANY) || !qname.is_subdomain_of(&self.apex)
+        {
+            return error(RCode::REFUSED);
+        }
+
+        if q.qtype == Type::AXFR {
+            if query.transport != Transport::Tcp || qname != self.apex {
+                return error(RCode::REFUSED);
+            }
+            return self.axfr(query);
+        }
+
+        let mut w = ResponseWriter::new(query.msg, query.question_end);
+        self.resolve(&mut w, &qname, q.qtype);
+        vec![w.finish()]
+    }
+}
@@ -0,0 +1,114 @@
+use anyhow::Result;
+use rsdns::message::RCode;
+use std::{fmt, io, process::ExitCode};
+
+/// Exit status documentation appended to `--help`.
+pub const HELP: &str = "\
+Exit status:
+   0  success
+   1  usage error, or any other error not listed below
+   9  no reply from the nameservers (network error or timeout)
+  10  no usable nameservers
+  11  a response could not be parsed
+  12  NXDOMAIN (with --fail-on-rcode)
+  13  SERVFAIL (with --fail-on-rcode)
+  14  any other response code but NOERROR (with --fail-on-rcode)
+  15  the compared responses differ (ch4 diff, --rerun)
+
+When several queries fail, the status of the first failure is reported.";
+
+/// The exit status of ch4.
+///
+/// The values are documented in `--help` and must not change.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Status {
+    Success = 0,
+    Usage = 1,
+    Network = 9,
+    NoServers = 10,
+    Parse = 11,
+    NxDomain = 12,
+    ServFail = 13,
+    RCode = 14,
+    Differ = 15,
+}
+
+impl Status {
+    /// Returns `self` if it is a failure, or `other` otherwise.
+    pub fn or(self, other: Status) -> Status {
+        if self == Status::Success { other } else { self }
+    }
+
+    /// Maps a response code to the status reported with `--fail-on-rcode`.
+    pub fn from_rcode(rcode: RCode) -> Status {
+        match rcode {
+            RCode::NOERROR => Status::Success,
+            RCode::NXDOMAIN => Status::NxDomain,
+            RCode::SERVFAIL => Status::ServFail,
+            _ => Status::RCode,
+        }
+    }
+
+    /// Classifies an error by its origin.
+    pub fn from_error(err: &anyhow::Error) -> Status {
+        if let Some(e) = err.downcast_ref::<Error>() {
+            return match e {
+                Error::Usage(_) => Status::Usage,
+                Error::NoServers(_) => Status::NoServers,
+            };
+        }
+        match err.downcast_ref::<rsdns::Error>() {
+            Some(rsdns::Error::IoError(_) | rsdns::Error::Timeout) => Status::Network,
+            Some(_) => Status::Parse,
+            None => Status::Usage,
+        }
+    }
+}
+
+impl From<Status> for ExitCode {
+    fn from(s: Status) -> Self {
+        ExitCode::from(s as u8)
+    }
+}
+
+/// An error detected before any query is sent.
+#[derive(Debug)]
+pub enum Error {
+    Usage(String),
+    NoServers(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Usage(msg) | Error::NoServers(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Checks if a query failed because the nameserver didn't respond in time.
+pub fn is_timeout(err: &anyhow::Error) -> bool {
+    match err.downcast_ref::<rsdns::Error>() {
+        Some(rsdns::Error::Timeout) => true,
+        Some(rsdns::Error::IoError(e)) => {
+            matches!(
+                e.kind(),
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+            )
+        }
+        _ => false,
+    }
+}
+
+/// Prints a fatal error, if any, and converts the result into the process exit code.
+pub fn report(res: Result<Status>) -> ExitCode {
+    match res {
+        Ok(status) => status.into(),
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            Status::from_error(&e).into()
+        }
+    }
+}
@@ -2,10 +2,23 @@ use zyn::TokenStream;
 
 pub fn render(is_async: bool) -> TokenStream {
     zyn::zyn! {
-        use crate::{args::Args, fmt::Format};
+        use crate::{
+            args::{Args, Command},
+            fmt::{self, Exchange, Format, save::{EncodedProtocol, Settings}},
+            import,
+            serve::{self, wire},
+            status::{self, Status},
+        };
         use anyhow::Result;
-        use rsdns::records::Class;
-        use std::time::SystemTime;
+        use rsdns::{
+            clients::ClientConfig,
+            records::{Class, Type},
+        };
+        use std::{
+            collections::HashMap,
+            net::SocketAddr,
+            time::{Duration, SystemTime},
+        };
 
         @if (is_async) {
             cfg_if::cfg_if! {
@@ -24,35 +37,223 @@ pub fn render(is_async: bool) -> TokenStream {
             use rsdns::clients::std::Client;
         }
 
-        pub @if (is_async) { async } fn main() -> Result<()> {
+        pub @if (is_async) { async } fn main() -> Result<Status> {
             let mut buf = [0u8; u16::MAX as usize];
 
             let args = Args::get()?;
+            match args.command {
+                Some(Command::Serve(ref serve_args)) => return serve::run(serve_args),
+                Some(Command::Diff(ref diff_args)) => return fmt::diff::run(diff_args),
+                Some(Command::Import(ref import_args)) => return import::run(import_args),
+                None => {}
+            }
+
             let mut format = Format::new(&args);
             if args.has_read_path() {
-                return format.read();
+                let status = format.read()?;
+                format.done()?;
+                return Ok(status);
+            }
+            if args.read_pcap.is_some() {
+                let status = format.read_pcap()?;
+                format.done()?;
+                return Ok(status);
             }
 
-            let mut client = Client::new(args.config.clone()) @if (is_async) { .await } ?;
+            if let Some(ref path) = args.rerun {
+                return rerun(&args, path, &mut buf) @if (is_async) { .await } ;
+            }
 
-            for qname in args.qnames.iter() {
-                let now = SystemTime::now();
-                let size = client
-                    .query_raw(qname, args.qtype(), Class::IN, &mut buf) @if (is_async) { .await } ?;
-                let elapsed = now.elapsed().expect("time failed");
-                format.add(
-                    Some(qname),
-                    Some(args.qtype()),
-                    &buf[..size],
-                    Some(args.config.nameserver()),
-                    Some(now),
-                    Some(elapsed),
-                )?;
+            let mut status = Status::Success;
+
+            if args.propagation {
+                let mut clients: Vec<Option<Client>> = args.resolvers.iter().map(|_| None).collect();
+                for qname in args.qnames.iter() {
+                    for (i, resolver) in args.resolvers.iter().enumerate() {
+                        status = status.or(query(
+                            &mut clients[i..=i],
+                            &[resolver.addr],
+                            &args,
+                            &mut format,
+                            qname,
+                            &mut buf,
+                        ) @if (is_async) { .await } ?);
+                    }
+                }
+                format.done()?;
+                return Ok(status);
+            }
+
+            let mut clients: Vec<Option<Client>> = args.nameservers.iter().map(|_| None).collect();
+            let mut iteration = 0u64;
+
+            loop {
+                for qname in args.qnames.iter() {
+                    status = status.or(query(
+                        &mut clients,
+                        &args.nameservers,
+                        &args,
+                        &mut format,
+                        qname,
+                        &mut buf,
+                    ) @if (is_async) { .await } ?);
+                }
+
+                iteration += 1;
+                match (args.watch, args.count) {
+                    (Some(_), Some(count)) if iteration >= count => break,
+                    (Some(interval), _) => {
+                        sleep(Duration::from_millis(interval)) @if (is_async) { .await };
+                    }
+                    (None, _) => break,
+                }
             }
 
             format.done()?;
 
-            Ok(())
+            Ok(status)
+        }
+
+        /// Queries the nameservers in turn until one of them responds.
+        ///
+        /// Failures are reported and returned as status, to let the remaining queries proceed.
+        @if (is_async) { async } fn query(
+            clients: &mut [Option<Client>],
+            nameservers: &[SocketAddr],
+            args: &Args,
+            format: &mut Format<'_>,
+            qname: &str,
+            buf: &mut [u8],
+        ) -> Result<Status> {
+            let last = nameservers.len() - 1;
+            for (i, ns) in nameservers.iter().enumerate() {
+                let now = SystemTime::now();
+                let res = exchange(&mut clients[i], &args.config, *ns, qname, args.qtype(), buf)
+                    @if (is_async) { .await } ;
+                match res {
+                    Ok(size) => {
+                        let elapsed = now.elapsed().expect("time failed");
+                        let msg = &buf[..size];
+                        let id = u16::from_be_bytes([msg[0], msg[1]]);
+                        let query = wire::client_query(&args.config, id, qname, args.qtype())?;
+                        let protocol = EncodedProtocol::new(&args.config, args.source, msg);
+                        let res = format.add(&Exchange {
+                            qname: Some(qname),
+                            qtype: Some(args.qtype()),
+                            query: Some(&query),
+                            query_reconstructed: true,
+                            msg,
+                            ns: Some(*ns),
+                            ts: Some(now),
+                            elapsed: Some(elapsed),
+                            protocol: Some(&protocol),
+                            cmd_line: None,
+                        });
+                        return match res {
+                            Ok(()) if args.fail_on_rcode => {
+                                Ok(Status::from_rcode(fmt::response_code(msg)?))
+                            }
+                            Ok(()) => Ok(Status::Success),
+                            Err(e) if Status::from_error(&e) == Status::Parse => {
+                                if !fmt::is_reported(&e) {
+                                    println!(";; failed to parse the response from {ns}: {e:#}");
+                                }
+                                Ok(Status::Parse)
+                            }
+                            Err(e) => Err(e),
+                        };
+                    }
+                    Err(e) if i < last => println!(";; communications error to {ns}: {e:#}"),
+                    Err(e) => {
+                        if args.is_watch() || args.propagation {
+                            format.add_error(qname, args.qtype(), *ns, now, &e)?;
+                        } else if status::is_timeout(&e) {
+                            println!(";; connection timed out; no servers could be reached");
+                        } else {
+                            println!(";; communications error to {ns}: {e:#}");
+                            println!(";; no servers could be reached");
+                        }
+                        return Ok(Status::from_error(&e));
+                    }
+                }
+            }
+            Ok(Status::Success)
+        }
+
+        /// Asks the questions of a saved file again and diffs the responses with the recorded ones.
+        ///
+        /// Questions go to the nameserver they were recorded from, unless nameservers are
+        /// given on the command line. They are asked with the recorded protocol strategy,
+        /// recursion and EDNS settings, unless others are given on the command line.
+        @if (is_async) { async } fn rerun(args: &Args, path: &str, buf: &mut [u8]) -> Result<Status> {
+            let mut rerun = fmt::diff::Rerun::load(path)?;
+            let questions = rerun.questions()?;
+            if args.nameservers_given {
+                println!(";; rerunning {} questions from {path} @{}", questions.len(), args.nameservers[0]);
+            } else {
+                println!(";; rerunning {} questions from {path}", questions.len());
+            }
+
+            let mut clients: HashMap<(SocketAddr, Settings), Option<Client>> = HashMap::new();
+            let mut status = Status::Success;
+            for (i, q) in questions.iter().enumerate() {
+                let nameservers = match q.nameserver.as_ref() {
+                    Some(ns) if !args.nameservers_given => std::slice::from_ref(ns),
+                    _ => args.nameservers.as_slice(),
+                };
+                let config = args.rerun_config(&q.settings);
+                // a question without a recorded nameserver needs one from the command line or the OS
+                let mut res = Err(status::Error::NoServers("no nameservers".to_string()).into());
+                for ns in nameservers.iter() {
+                    let client = clients.entry((*ns, q.settings)).or_default();
+                    res = exchange(client, &config, *ns, &q.qname, q.qtype, buf)
+                        @if (is_async) { .await } ;
+                    if res.is_ok() {
+                        break;
+                    }
+                }
+                if let Err(e) = res.and_then(|size| rerun.add(i, &buf[..size])) {
+                    status = status.or(Status::from_error(&e));
+                    rerun.add_error(i, &e);
+                }
+            }
+            Ok(status.or(rerun.done()?))
+        }
+
+        /// Sends a single query, creating the nameserver client on first use.
+        @if (is_async) { async } fn exchange(
+            client: &mut Option<Client>,
+            config: &ClientConfig,
+            ns: SocketAddr,
+            qname: &str,
+            qtype: Type,
+            buf: &mut [u8],
+        ) -> Result<usize> {
+            if client.is_none() {
+                let config = config.clone().set_nameserver(ns);
+                *client = Some(Client::new(config) @if (is_async) { .await } ?);
+            }
+            let client = client.as_mut().expect("client is set");
+            Ok(client.query_raw(qname, qtype, Class::IN, buf) @if (is_async) { .await } ?)
+        }
+
+        @if (is_async) {
+            async fn sleep(duration: Duration) {
+                cfg_if::cfg_if! {
+                    if #[cfg(feature = "net-tokio")] {
+                        tokio::time::sleep(duration).await;
+                    } else if #[cfg(feature = "net-async-std")] {
+                        async_std::task::sleep(duration).await;
+                    } else if #[cfg(feature = "net-smol")] {
+                        smol::Timer::after(duration).await;
+                    }
+                }
+            }
+        }
+        @else {
+            fn sleep(duration: Duration) {
+                std::thread::sleep(duration);
+            }
         }
     }
     .into()